@@ -0,0 +1,82 @@
+//! Compare the collision index with checking every collider. Run with
+//! `cargo bench --bench collision`.
+
+use bevy::{ecs::entity::Entity, math::Vec3};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use sauerstoff::{
+    components::collision::Collision,
+    data::{
+        common::{Position, Rect, Size},
+        entity_types::{CollisionShape, CollisionShapes},
+    },
+    resources::collision_index::CollisionIndex,
+};
+
+const SIZE: Size = Size {
+    width: 100,
+    height: 60,
+};
+
+fn collider(translation: Vec3) -> Collision {
+    let shapes = CollisionShapes(vec![CollisionShape::Box(Rect {
+        position: Position { x: 0, y: 0 },
+        size: SIZE,
+    })]);
+    let mut collision = Collision::from_data(SIZE, &shapes);
+    collision.update_position(translation);
+    collision
+}
+
+/// Colliders on a square grid around the origin
+fn colliders(count: usize) -> Vec<Collision> {
+    let columns = (count as f32).sqrt().ceil() as usize;
+    (0..count)
+        .map(|i| {
+            let (column, row) = ((i % columns) as f32, (i / columns) as f32);
+            let offset = columns as f32 * 150.0;
+            collider(Vec3::new(
+                column * 300.0 - offset,
+                row * 300.0 - offset,
+                0.0,
+            ))
+        })
+        .collect()
+}
+
+fn entity_collision(c: &mut Criterion) {
+    let player = collider(Vec3::ZERO);
+    let position = Vec3::new(20.0, 20.0, 0.0);
+    let mut group = c.benchmark_group("entity_collision");
+    for count in [10, 100, 1000, 10000] {
+        let colliders = colliders(count);
+        let mut index = CollisionIndex::default();
+        for (i, collision) in colliders.iter().enumerate() {
+            let (min, max) = collision.world_bounds();
+            index.insert(Entity::from_raw(i as u32), min, max);
+        }
+        group.bench_with_input(BenchmarkId::new("linear", count), &count, |b, _| {
+            b.iter(|| {
+                colliders
+                    .iter()
+                    .filter_map(|other| player.collide(black_box(position), other))
+                    .count()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("index", count), &count, |b, _| {
+            b.iter(|| {
+                let (min, max) = player.bounds_at(black_box(position));
+                index
+                    .query(min, max)
+                    .into_iter()
+                    .filter_map(|entity| {
+                        player.collide(position, &colliders[entity.index() as usize])
+                    })
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, entity_collision);
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+//! Bake the navigation meshes of all maps and store them next to the
+//! map files, so the game doesn't have to bake them when a map is
+//! loaded. This has to be run again after changing a map or the entity
+//! types placed on it, e.g. with `make nav-meshes`. `sauerstoff-check`
+//! reports meshes which are outdated. Maps streamed in chunks are not
+//! baked.
+
+use sauerstoff::{
+    data::error::LoadErrors,
+    resources::navmesh::{NavMesh, NAV_MESH_AGENT_RADIUS},
+    run_headless, static_nav_grids,
+};
+
+fn main() {
+    run_headless(|world| {
+        let mut errors = LoadErrors::default();
+        for (path, grid) in static_nav_grids(world, &mut errors) {
+            if errors
+                .collect(NavMesh::bake(&grid, NAV_MESH_AGENT_RADIUS).write(&path))
+                .is_some()
+            {
+                println!("Baked {}", path.display());
+            }
+        }
+        if !errors.is_empty() {
+            eprint!("{}", errors);
+            std::process::exit(1);
+        }
+    });
+}
@@ -0,0 +1,32 @@
+//! Check the game data for errors without opening a window. This is
+//! meant to be run before committing changes to the assets. The data
+//! is loaded and checked by the same systems as in the game. Baked
+//! navigation meshes which don't match their map anymore are reported
+//! as well.
+
+use sauerstoff::{
+    data::error::{LoadError, LoadErrors},
+    resources::navmesh::{NavMesh, NAV_MESH_AGENT_RADIUS},
+    run_headless, static_nav_grids,
+};
+
+fn main() {
+    run_headless(|world| {
+        let mut errors = LoadErrors::default();
+        for (path, grid) in static_nav_grids(world, &mut errors) {
+            match NavMesh::load(&path, NAV_MESH_AGENT_RADIUS) {
+                Ok(nav_mesh) if !nav_mesh.is_baked_from(&grid) => {
+                    errors.push(LoadError::OutdatedNavMesh { path });
+                }
+                // Maps without baked mesh are baked by the game
+                Ok(_) | Err(LoadError::MissingFile { .. }) => {}
+                Err(error) => errors.push(error),
+            }
+        }
+        if !errors.is_empty() {
+            eprint!("{}", errors);
+            std::process::exit(1);
+        }
+        println!("No errors found");
+    });
+}
@@ -2,6 +2,9 @@ use std::time::Duration;
 
 use bevy::{prelude::Component, utils::HashMap};
 
+/// Name of the animation used for entity types with a single animation
+pub const DEFAULT_ANIMATION: &str = "default";
+
 #[derive(Component, Debug)]
 pub struct Animation {
     pub frames: HashMap<String, Vec<(usize, Duration)>>,
@@ -9,15 +12,15 @@ pub struct Animation {
 
 #[derive(Component, Debug)]
 pub struct AnimationState {
-    pub animation: &'static str,
+    pub animation: String,
     pub restart: bool,
     pub index: usize,
 }
 
 impl AnimationState {
-    pub fn start(&mut self, animation: &'static str) {
+    pub fn start(&mut self, animation: &str) {
         if animation != self.animation {
-            self.animation = animation;
+            self.animation = animation.to_owned();
             self.restart = true;
             self.index = 0;
         }
@@ -1,80 +1,433 @@
 use bevy::{
     math::{Vec2, Vec3},
     prelude::Component,
-    sprite::collide_aabb::{collide, Collision as BevyCollision},
 };
 
 use crate::{
-    data::common::{Rect, Size},
+    data::{
+        common::Size,
+        entity_types::{CollisionLayer, CollisionShape, CollisionShapes},
+    },
     helpers::z_index,
+    resources::geometry::intersect,
 };
 
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone)]
 pub struct Collision {
+    /// Center of the bounding box of all shapes relative to the entity
+    /// translation
     pub origin: Vec3,
     pub pos: Vec3,
-    pub size: Vec2,
+    /// Shapes relative to the entity translation
+    pub shapes: Vec<Shape>,
+    /// Bit of the `CollisionLayer` of this collider
+    pub layer: u32,
+    /// Bits of the layers this collider collides with
+    pub mask: u32,
+    /// Sensors detect overlapping colliders but never block them
+    pub sensor: bool,
 }
 
-impl Collision {
-    pub fn from_data(entity_size: Size, collision_box: Rect) -> Self {
-        // The collision box in the data uses a right handed coordinate
-        // system with 0:0 in the lower left corner. Bevy uses a left
-        // handed coordinate system with 0:0 in the center. The origin
-        // can be added to the entity translation to calculate the bevy
-        // position of the bounding box.
-        let origin = Vec3::new(
-            -f32::from(entity_size.width) / 2.0
-                + f32::from(collision_box.size.width) / 2.0
-                + f32::from(collision_box.position.x),
-            // FIXME this expression can be simplified
-            f32::from(entity_size.height) / 2.0
-                - f32::from(collision_box.size.height) / 2.0
-                - f32::from(collision_box.position.y),
-            0.0,
-        );
-        Self {
-            origin,
-            pos: origin,
-            size: Vec2::new(
-                collision_box.size.width.into(),
-                collision_box.size.height.into(),
+/// Collision shape in bevy coordinates
+#[derive(Debug, Clone)]
+pub enum Shape {
+    /// Convex polygon. Boxes are polygons with 4 points.
+    Polygon(Vec<Vec2>),
+    Circle {
+        center: Vec2,
+        radius: f32,
+    },
+}
+
+impl Shape {
+    fn from_data(entity_size: Size, shape: &CollisionShape) -> Self {
+        // The shapes in the data use pixel coordinates with 0:0 in the
+        // upper left corner of the entity. Bevy uses 0:0 in the center
+        // with the y axis pointing up.
+        let to_bevy = |x: f32, y: f32| {
+            Vec2::new(
+                x - f32::from(entity_size.width) / 2.0,
+                f32::from(entity_size.height) / 2.0 - y,
+            )
+        };
+        match shape {
+            CollisionShape::Box(rect) => {
+                let (x, y) = (f32::from(rect.position.x), f32::from(rect.position.y));
+                let (width, height) = (f32::from(rect.size.width), f32::from(rect.size.height));
+                Self::Polygon(vec![
+                    to_bevy(x, y),
+                    to_bevy(x + width, y),
+                    to_bevy(x + width, y + height),
+                    to_bevy(x, y + height),
+                ])
+            }
+            CollisionShape::Circle { center, radius } => Self::Circle {
+                center: to_bevy(center.x.into(), center.y.into()),
+                radius: (*radius).into(),
+            },
+            CollisionShape::Polygon { points } => Self::Polygon(
+                points
+                    .iter()
+                    .map(|point| to_bevy(point.x.into(), point.y.into()))
+                    .collect(),
             ),
         }
     }
+
+    fn map_points(&self, f: impl Fn(Vec2) -> Vec2) -> Self {
+        match self {
+            Self::Polygon(points) => Self::Polygon(points.iter().copied().map(f).collect()),
+            Self::Circle { center, radius } => Self::Circle {
+                center: f(*center),
+                radius: *radius,
+            },
+        }
+    }
+
+    pub fn translated(&self, offset: Vec2) -> Self {
+        self.map_points(|point| point + offset)
+    }
+
+    /// Lower left and upper right corner of the bounding box
+    pub fn bounds(&self) -> (Vec2, Vec2) {
+        match self {
+            Self::Polygon(points) => points.iter().fold(
+                (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+                |(min, max), point| (min.min(*point), max.max(*point)),
+            ),
+            Self::Circle { center, radius } => (
+                *center - Vec2::splat(*radius),
+                *center + Vec2::splat(*radius),
+            ),
+        }
+    }
+
+    /// Check if a point is inside of the shape or on its outline
+    pub fn contains(&self, point: Vec2) -> bool {
+        match self {
+            Self::Polygon(points) => {
+                // The point is on the same side of all edges of a convex
+                // polygon
+                let (min, max) = (0..points.len())
+                    .map(|i| {
+                        (points[(i + 1) % points.len()] - points[i]).perp_dot(point - points[i])
+                    })
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), side| {
+                        (min.min(side), max.max(side))
+                    });
+                min >= 0.0 || max <= 0.0
+            }
+            Self::Circle { center, radius } => point.distance_squared(*center) <= radius * radius,
+        }
+    }
+
+    /// Position of the first intersection of the line from `start` to
+    /// `end` with the outline, from 0 (`start`) to 1 (`end`). Lines
+    /// starting inside of the shape don't hit it.
+    fn raycast(&self, start: Vec2, end: Vec2) -> Option<f32> {
+        if self.contains(start) {
+            return None;
+        }
+        match self {
+            Self::Polygon(points) => (0..points.len())
+                .filter_map(|i| intersect(start, end, points[i], points[(i + 1) % points.len()]))
+                .reduce(f32::min),
+            Self::Circle { center, radius } => {
+                // Solve |start + t * direction - center| = radius
+                let (direction, offset) = (end - start, start - *center);
+                let a = direction.length_squared();
+                let b = 2.0 * offset.dot(direction);
+                let c = offset.length_squared() - radius * radius;
+                let discriminant = b * b - 4.0 * a * c;
+                if a == 0.0 || discriminant < 0.0 {
+                    return None;
+                }
+                let t = (-b - discriminant.sqrt()) / (2.0 * a);
+                (0.0..=1.0).contains(&t).then_some(t)
+            }
+        }
+    }
+
+    /// Interval covered by the shape on an axis
+    fn project(&self, axis: Vec2) -> (f32, f32) {
+        match self {
+            Self::Polygon(points) => {
+                points
+                    .iter()
+                    .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), point| {
+                        let projection = point.dot(axis);
+                        (min.min(projection), max.max(projection))
+                    })
+            }
+            Self::Circle { center, radius } => {
+                let projection = center.dot(axis);
+                (projection - radius, projection + radius)
+            }
+        }
+    }
+
+    /// Axes which may separate this shape from the other shape: the
+    /// edge normals of polygons and for circles the direction to the
+    /// closest point of the other shape
+    fn axes(&self, other: &Self) -> Vec<Vec2> {
+        match self {
+            Self::Polygon(points) => (0..points.len())
+                .map(|i| (points[(i + 1) % points.len()] - points[i]).perp())
+                .collect(),
+            Self::Circle { center, .. } => {
+                let closest = match other {
+                    Self::Polygon(points) => points.iter().copied().min_by(|a, b| {
+                        a.distance_squared(*center)
+                            .total_cmp(&b.distance_squared(*center))
+                    }),
+                    Self::Circle { center, .. } => Some(*center),
+                };
+                closest
+                    .map(|closest| closest - *center)
+                    .into_iter()
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Find the shortest movement of `a` which separates it from `b` with
+/// the separating axis theorem. `None` is returned if the shapes don't
+/// overlap.
+fn separate(a: &Shape, b: &Shape) -> Option<Vec2> {
+    let mut axes = a
+        .axes(b)
+        .into_iter()
+        .chain(b.axes(a))
+        .map(Vec2::normalize_or_zero)
+        .filter(|axis| *axis != Vec2::ZERO)
+        .collect::<Vec<_>>();
+    if axes.is_empty() {
+        // Circles with the same center have no axis, any direction
+        // separates them
+        axes.push(Vec2::X);
+    }
+    let mut shortest: Option<Vec2> = None;
+    for axis in axes {
+        let (min_a, max_a) = a.project(axis);
+        let (min_b, max_b) = b.project(axis);
+        // Distances to move `a` along the axis or in the opposite
+        // direction until the intervals don't overlap anymore. This
+        // also works if one interval contains the other one.
+        let (forward, backward) = (max_b - min_a, max_a - min_b);
+        if forward <= 0.0 || backward <= 0.0 {
+            return None;
+        }
+        let push = if forward < backward {
+            axis * forward
+        } else {
+            -axis * backward
+        };
+        if !shortest.is_some_and(|shortest| shortest.length_squared() <= push.length_squared()) {
+            shortest = Some(push);
+        }
+    }
+    shortest
+}
+
+impl Collision {
+    pub fn from_data(entity_size: Size, shapes: &CollisionShapes) -> Self {
+        let shapes = shapes
+            .0
+            .iter()
+            .map(|shape| Shape::from_data(entity_size, shape))
+            .collect();
+        let mut collision = Self {
+            origin: Vec3::ZERO,
+            pos: Vec3::ZERO,
+            shapes,
+            layer: CollisionLayer::default().bit(),
+            mask: CollisionLayer::mask(&CollisionLayer::ALL),
+            sensor: false,
+        };
+        collision.update_origin();
+        collision
+    }
+    /// Set the origin to the center of the bounding box. The origin
+    /// can be added to the entity translation to calculate the bevy
+    /// position of the bounding box.
+    fn update_origin(&mut self) {
+        let (min, max) = self.bounds();
+        self.origin = if self.shapes.is_empty() {
+            Vec3::ZERO
+        } else {
+            ((min + max) / 2.0).extend(0.0)
+        };
+        self.pos = self.origin;
+    }
+    /// Lower left and upper right corner of the bounding box of all
+    /// shapes relative to the entity translation
+    pub fn bounds(&self) -> (Vec2, Vec2) {
+        self.shapes.iter().map(Shape::bounds).fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), (shape_min, shape_max)| (min.min(shape_min), max.max(shape_max)),
+        )
+    }
+    /// Bounding box of all shapes if the entity is at the given
+    /// translation
+    pub fn bounds_at(&self, translation: Vec3) -> (Vec2, Vec2) {
+        let (min, max) = self.bounds();
+        (min + translation.truncate(), max + translation.truncate())
+    }
+    /// Bounding box of all shapes in world coordinates
+    pub fn world_bounds(&self) -> (Vec2, Vec2) {
+        self.bounds_at(self.pos - self.origin)
+    }
+    /// Mirror and scale the collision shapes together with the entity
+    pub fn transform(&mut self, scale: f32, flip_x: bool) {
+        let mirror = Vec2::new(if flip_x { -1.0 } else { 1.0 }, 1.0);
+        for shape in self.shapes.iter_mut() {
+            *shape = shape.map_points(|point| point * mirror * scale);
+            if let Shape::Circle { radius, .. } = shape {
+                *radius *= scale;
+            }
+        }
+        self.update_origin();
+    }
     pub fn update_position(&mut self, translation: Vec3) -> f32 {
         self.pos = translation + self.origin;
         z_index(self.pos.y)
     }
+    /// Check if the layers and masks of both colliders allow them to
+    /// collide
+    pub fn interacts(&self, other: &Self) -> bool {
+        self.mask & other.layer != 0 && other.mask & self.layer != 0
+    }
+    /// Check if the other collider blocks the movement of this one
+    pub fn blocks(&self, other: &Self) -> bool {
+        !self.sensor && !other.sensor && self.interacts(other)
+    }
+    /// Check if any shapes of both colliders overlap at their current
+    /// positions
+    pub fn overlaps(&self, other: &Self) -> bool {
+        let translation = (self.pos - self.origin).truncate();
+        let other_translation = (other.pos - other.origin).truncate();
+        self.shapes.iter().any(|shape| {
+            let shape = shape.translated(translation);
+            other.shapes.iter().any(|other_shape| {
+                separate(&shape, &other_shape.translated(other_translation)).is_some()
+            })
+        })
+    }
+    /// Check if any shape overlaps the rectangle given by its lower
+    /// left and upper right corner
+    pub fn overlaps_area(&self, min: Vec2, max: Vec2) -> bool {
+        let area = Shape::Polygon(vec![
+            min,
+            Vec2::new(max.x, min.y),
+            max,
+            Vec2::new(min.x, max.y),
+        ]);
+        let translation = (self.pos - self.origin).truncate();
+        self.shapes
+            .iter()
+            .any(|shape| separate(&shape.translated(translation), &area).is_some())
+    }
+    /// Check if any shape contains the point given in world
+    /// coordinates
+    pub fn contains(&self, point: Vec2) -> bool {
+        let point = point - (self.pos - self.origin).truncate();
+        self.shapes.iter().any(|shape| shape.contains(point))
+    }
+    /// Position of the first hit of the line from `start` to `end` with
+    /// any shape, from 0 (`start`) to 1 (`end`). Shapes containing the
+    /// start are ignored, so entities can cast rays from their own
+    /// position.
+    pub fn raycast(&self, start: Vec2, end: Vec2) -> Option<f32> {
+        let translation = (self.pos - self.origin).truncate();
+        self.shapes
+            .iter()
+            .filter_map(|shape| shape.raycast(start - translation, end - translation))
+            .reduce(f32::min)
+    }
     /// Check collision with annother `Collision` object
     /// and return the new entity transformation if a collision
-    /// was detected.
-    pub fn collide(&mut self, translation: Vec3, other: &Self) -> Option<Vec3> {
-        collide(translation + self.origin, self.size, other.pos, other.size).map(|collision| {
-            match collision {
-                BevyCollision::Left => Vec3::new(
-                    other.pos.x - other.size.x / 2.0 - self.size.x / 2.0 - self.origin.x,
-                    translation.y,
-                    translation.z,
-                ),
-                BevyCollision::Right => Vec3::new(
-                    other.pos.x + other.size.x / 2.0 + self.size.x / 2.0 - self.origin.x,
-                    translation.y,
-                    translation.z,
-                ),
-                BevyCollision::Top => Vec3::new(
-                    translation.x,
-                    other.pos.y + other.size.y / 2.0 + self.size.y / 2.0 - self.origin.y,
-                    translation.z,
-                ),
-                BevyCollision::Bottom => Vec3::new(
-                    translation.x,
-                    other.pos.y - other.size.y / 2.0 - self.size.y / 2.0 - self.origin.y,
-                    translation.z,
-                ),
-                // FIXME How to handle inside collision?
-                BevyCollision::Inside => translation,
+    /// was detected. Every overlapping pair of shapes pushes the
+    /// entity out by the shortest possible distance, which also
+    /// resolves entities inside of the other entity.
+    pub fn collide(&self, translation: Vec3, other: &Self) -> Option<Vec3> {
+        if !self.blocks(other) {
+            return None;
+        }
+        let other_translation = (other.pos - other.origin).truncate();
+        let mut offset = translation.truncate();
+        let mut collided = false;
+        for shape in self.shapes.iter() {
+            for other_shape in other.shapes.iter() {
+                if let Some(push) = separate(
+                    &shape.translated(offset),
+                    &other_shape.translated(other_translation),
+                ) {
+                    offset += push;
+                    collided = true;
+                }
             }
-        })
+        }
+        collided.then(|| offset.extend(translation.z))
     }
 }
+
+#[test]
+fn test_collision_shapes() {
+    let size = Size {
+        width: 100,
+        height: 100,
+    };
+    let shapes = |yaml: &str| {
+        let shapes: CollisionShapes = serde_yaml::from_str(yaml).unwrap();
+        Collision::from_data(size, &shapes)
+    };
+    let mut wall = shapes(
+        "[{shape: box, x: 0, y: 40, width: 100, height: 20},
+          {shape: circle, x: 50, y: 20, radius: 10}]",
+    );
+    wall.update_position(Vec3::ZERO);
+    assert_eq!(wall.origin, Vec3::new(0.0, 15.0, 0.0));
+    let mut player = shapes("{x: 40, y: 40, width: 20, height: 20}");
+
+    // Walking into the box from below is stopped at its edge
+    let position = player.collide(Vec3::new(0.0, -15.0, 0.0), &wall).unwrap();
+    assert!((position - Vec3::new(0.0, -20.0, 0.0)).length() < 0.001);
+    assert_eq!(player.collide(Vec3::new(0.0, -25.0, 0.0), &wall), None);
+
+    // Entities completely inside are pushed out
+    let position = player.collide(Vec3::new(3.0, 0.0, 0.0), &wall).unwrap();
+    assert!(position.y.abs() >= 20.0 - 0.001);
+
+    // The circle is round
+    player.transform(0.5, true);
+    assert_eq!(player.collide(Vec3::new(-12.5, 42.5, 0.0), &wall), None);
+    assert!(player.collide(Vec3::new(-5.0, 36.0, 0.0), &wall).is_some());
+
+    // Sensors and colliders on other layers don't block
+    player.mask = CollisionLayer::mask(&[CollisionLayer::Npc]);
+    assert_eq!(player.collide(Vec3::new(-5.0, 36.0, 0.0), &wall), None);
+    player.mask = CollisionLayer::mask(&CollisionLayer::ALL);
+    wall.sensor = true;
+    assert_eq!(player.collide(Vec3::new(-5.0, 36.0, 0.0), &wall), None);
+    player.update_position(Vec3::new(-5.0, 36.0, 0.0));
+    assert!(wall.interacts(&player) && wall.overlaps(&player));
+
+    // A convex polygon and a concave one
+    let triangle: CollisionShapes = serde_yaml::from_str(
+        "[{shape: polygon, points: [{x: 0, y: 0}, {x: 9, y: 0}, {x: 0, y: 9}]}]",
+    )
+    .unwrap();
+    assert!(triangle.0[0].is_valid());
+    let arrow: CollisionShapes = serde_yaml::from_str(
+        "[{shape: polygon, points: [{x: 0, y: 0}, {x: 9, y: 4}, {x: 0, y: 9}, {x: 4, y: 4}]}]",
+    )
+    .unwrap();
+    assert!(!arrow.0[0].is_valid());
+
+    // Circles with the same center are pushed apart as well
+    let mut circle = shapes("[{shape: circle, x: 50, y: 50, radius: 10}]");
+    circle.update_position(Vec3::ZERO);
+    let position = circle.collide(Vec3::ZERO, &circle).unwrap();
+    assert!((position.truncate().length() - 20.0).abs() < 0.001);
+}
@@ -0,0 +1,7 @@
+use bevy::prelude::Component;
+
+/// Name of the entity type an entity was spawned from
+#[derive(Component, Debug)]
+pub struct EntityTypeName {
+    pub name: String,
+}
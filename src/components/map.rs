@@ -0,0 +1,28 @@
+use bevy::prelude::Component;
+
+use crate::{data::map::EntityOverrides, resources::chunks::ChunkCoord};
+
+/// Marker for entities spawned from the map data
+#[derive(Component, Debug)]
+pub struct MapEntity {
+    pub name: String,
+    /// The overrides are kept so they can be applied again when the
+    /// entity type is reloaded.
+    pub overrides: EntityOverrides,
+}
+
+/// Marker for the background sprite of the current map
+#[derive(Component, Debug)]
+pub struct MapBackground;
+
+/// Marker for the full screen node fading the screen to black while
+/// the map changes
+#[derive(Component, Debug)]
+pub struct FadeOverlay;
+
+/// Background sprite of one chunk of a map streamed in chunks. The
+/// entity is a `MapBackground` as well.
+#[derive(Component, Debug)]
+pub struct BackgroundChunk {
+    pub chunk: ChunkCoord,
+}
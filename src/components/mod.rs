@@ -1,6 +1,10 @@
 pub mod animation;
 pub mod collision;
+pub mod entity_type;
 pub mod followcam;
 pub mod interaction;
 pub mod item;
+pub mod map;
+pub mod navigation;
 pub mod player;
+pub mod trigger;
@@ -0,0 +1,27 @@
+use bevy::{math::Vec3, prelude::Component, tasks::Task};
+
+use crate::resources::pathfinding::Pathfinding;
+
+/// Path search running in the background. It is replaced by a
+/// `PathResult` by the `poll_path_queries` system once the search is
+/// finished.
+#[derive(Component, Debug)]
+pub struct PathQuery {
+    pub task: Task<Option<Vec<Vec3>>>,
+}
+
+impl PathQuery {
+    pub fn new(pathfinding: &Pathfinding, start: Vec3, goal: Vec3, agent_radius: f32) -> Self {
+        Self {
+            task: pathfinding.find_path_async(start, goal, agent_radius),
+        }
+    }
+}
+
+/// Result of a `PathQuery`
+#[derive(Component, Debug)]
+pub struct PathResult {
+    /// Waypoints after the start position or `None` if the goal can't
+    /// be reached
+    pub waypoints: Option<Vec<Vec3>>,
+}
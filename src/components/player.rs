@@ -9,6 +9,27 @@ use bevy::{
     },
 };
 
+use crate::resources::map::Terrain;
+
+/// Entity type used for the player
+pub const PLAYER_ENTITY_TYPE: &str = "wolfgang";
+
+/// Position where the player enters the map
+pub const PLAYER_SPAWN: Vec3 = Vec3::ZERO;
+
+/// Animations the player entity type must provide. There are no
+/// `interact_up` and `interact_down` animations, yet. See the
+/// `interact_direction` hack in the `player_system`.
+pub const PLAYER_ANIMATIONS: [&str; 7] = [
+    "idle",
+    "walk_right",
+    "walk_left",
+    "walk_up",
+    "walk_down",
+    "interact_left",
+    "interact_right",
+];
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum PlayerState {
     Idle,
@@ -133,6 +154,8 @@ pub struct Player {
     pub interact_direction: InteractDirection,
     pub direction: PlayerDirection,
     pub center: Vec3,
+    /// Terrain below the player, e.g. to choose footstep sounds
+    pub terrain: Terrain,
 }
 
 impl Player {
@@ -164,6 +187,7 @@ impl Default for Player {
             direction: PlayerDirection::Right,
             interact_direction: InteractDirection::Right,
             center: Vec3::new(0.0, -40.0, 0.0),
+            terrain: Terrain::Walkable,
         }
     }
 }
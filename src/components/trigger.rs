@@ -0,0 +1,23 @@
+use bevy::{
+    prelude::{Component, Entity},
+    utils::HashSet,
+};
+
+/// Trigger zone spawned from the map data. The zone itself is a sensor
+/// `Collision`.
+#[derive(Component, Debug)]
+pub struct Trigger {
+    /// Name sent with the trigger events
+    pub name: String,
+    /// Entities currently inside of the zone
+    pub inside: HashSet<Entity>,
+}
+
+impl Trigger {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            inside: HashSet::default(),
+        }
+    }
+}
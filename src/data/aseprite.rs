@@ -0,0 +1,270 @@
+use std::{fmt, marker::PhantomData, path::Path, time::Duration};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    reflect::{TypePath, TypeUuid},
+    utils::{BoxedFuture, HashMap},
+};
+use serde::{
+    de::{MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
+
+use super::error::LoadError;
+
+/// Sprite sheet exported by Aseprite (`File > Export Sprite Sheet`
+/// with JSON data enabled). Both the "Hash" and "Array" variants of
+/// the JSON format are supported. The sheets are assets, so the asset
+/// server watches them as dependencies of the entity types using them.
+#[derive(Deserialize, Debug, Clone, TypeUuid, TypePath)]
+#[uuid = "5b413569-fb62-464c-a7ab-72f29058fa53"]
+pub struct AsepriteSheet {
+    pub frames: AsepriteFrames,
+    pub meta: AsepriteMeta,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum AsepriteFrames {
+    Array(Vec<AsepriteFrame>),
+    #[serde(deserialize_with = "deserialize_ordered")]
+    Hash(Vec<AsepriteFrame>),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AsepriteFrame {
+    pub frame: AsepriteRect,
+    /// Duration in milliseconds
+    pub duration: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct AsepriteRect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct AsepriteSize {
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AsepriteMeta {
+    /// Path of the sprite sheet image relative to the JSON file
+    pub image: String,
+    pub size: AsepriteSize,
+    #[serde(rename = "frameTags", default)]
+    pub frame_tags: Vec<AsepriteTag>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AsepriteTag {
+    pub name: String,
+    pub from: usize,
+    pub to: usize,
+    #[serde(default)]
+    pub direction: AsepriteDirection,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AsepriteDirection {
+    #[default]
+    Forward,
+    Reverse,
+    Pingpong,
+    PingpongReverse,
+}
+
+impl AsepriteFrames {
+    pub fn as_slice(&self) -> &[AsepriteFrame] {
+        match self {
+            Self::Array(frames) => frames,
+            Self::Hash(frames) => frames,
+        }
+    }
+}
+
+impl AsepriteTag {
+    /// Indices of the frames played by this tag in playback order
+    pub fn frame_indices(&self) -> Vec<usize> {
+        let forward = self.from..=self.to;
+        match self.direction {
+            AsepriteDirection::Forward => forward.collect(),
+            AsepriteDirection::Reverse => forward.rev().collect(),
+            AsepriteDirection::Pingpong => forward
+                .clone()
+                .chain(
+                    forward
+                        .rev()
+                        .skip(1)
+                        .take((self.to - self.from).saturating_sub(1)),
+                )
+                .collect(),
+            AsepriteDirection::PingpongReverse => forward
+                .clone()
+                .rev()
+                .chain(
+                    forward
+                        .skip(1)
+                        .take((self.to - self.from).saturating_sub(1)),
+                )
+                .collect(),
+        }
+    }
+}
+
+impl AsepriteSheet {
+    /// Parse the JSON file and check that all tags play existing frames
+    pub fn from_bytes(path: &Path, bytes: &[u8]) -> Result<Self, LoadError> {
+        let sheet: Self = serde_json::from_slice(bytes).map_err(|e| LoadError::json(path, e))?;
+        let frame_count = sheet.frames.as_slice().len();
+        for tag in sheet.meta.frame_tags.iter() {
+            if tag.from > tag.to || tag.to >= frame_count {
+                return Err(LoadError::InvalidFrameRange {
+                    path: path.to_owned(),
+                    animation: tag.name.clone(),
+                    first: tag.from,
+                    last: tag.to,
+                    frame_count,
+                });
+            }
+        }
+        Ok(sheet)
+    }
+
+    /// Frames of all tags as (frame index, duration) pairs. Sheets
+    /// without tags are played as one animation with the given
+    /// `default_name`.
+    pub fn animations(&self, default_name: &str) -> HashMap<String, Vec<(usize, Duration)>> {
+        let frames = self.frames.as_slice();
+        let with_duration = |indices: Vec<usize>| {
+            indices
+                .into_iter()
+                .map(|index| (index, Duration::from_millis(frames[index].duration)))
+                .collect()
+        };
+        if self.meta.frame_tags.is_empty() {
+            return [(
+                default_name.to_owned(),
+                with_duration((0..frames.len()).collect()),
+            )]
+            .into_iter()
+            .collect();
+        }
+        self.meta
+            .frame_tags
+            .iter()
+            .map(|tag| (tag.name.clone(), with_duration(tag.frame_indices())))
+            .collect()
+    }
+}
+
+/// Deserialize the values of a map in the order they appear in the
+/// file. The "Hash" format uses the frame names as keys and relies on
+/// their order for the frame indices.
+fn deserialize_ordered<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct OrderedVisitor<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for OrderedVisitor<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map of frames")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut values = Vec::with_capacity(map.size_hint().unwrap_or(0));
+            while let Some((_, value)) = map.next_entry::<String, T>()? {
+                values.push(value);
+            }
+            Ok(values)
+        }
+    }
+
+    deserializer.deserialize_map(OrderedVisitor(PhantomData))
+}
+
+/// Loads the JSON files exported by Aseprite. Errors are not reported
+/// here, the entity type loader parses the JSON as well and reports
+/// them for the entity type.
+#[derive(Default)]
+pub struct AsepriteSheetLoader;
+
+impl AssetLoader for AsepriteSheetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let sheet = AsepriteSheet::from_bytes(load_context.path(), bytes)?;
+            load_context.set_default_asset(LoadedAsset::new(sheet));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["json"]
+    }
+}
+
+#[test]
+fn test_aseprite_tags() {
+    let json = r#"{
+        "frames": {
+            "w 0.aseprite": { "frame": { "x": 0, "y": 0, "w": 8, "h": 8 }, "duration": 100 },
+            "w 1.aseprite": { "frame": { "x": 8, "y": 0, "w": 8, "h": 8 }, "duration": 200 },
+            "w 2.aseprite": { "frame": { "x": 16, "y": 0, "w": 8, "h": 8 }, "duration": 300 }
+        },
+        "meta": {
+            "image": "w.png",
+            "size": { "w": 24, "h": 8 },
+            "frameTags": [
+                { "name": "idle", "from": 0, "to": 2, "direction": "pingpong" },
+                { "name": "back", "from": 1, "to": 2, "direction": "reverse" }
+            ]
+        }
+    }"#;
+    let sheet: AsepriteSheet = serde_json::from_str(json).unwrap();
+    let animations = sheet.animations("default");
+    let ms = Duration::from_millis;
+    assert_eq!(
+        animations["idle"],
+        vec![(0, ms(100)), (1, ms(200)), (2, ms(300)), (1, ms(200))]
+    );
+    assert_eq!(animations["back"], vec![(2, ms(300)), (1, ms(200))]);
+}
+
+#[test]
+fn test_aseprite_tag_out_of_range() {
+    let json = r#"{
+        "frames": [
+            { "frame": { "x": 0, "y": 0, "w": 8, "h": 8 }, "duration": 100 },
+            { "frame": { "x": 8, "y": 0, "w": 8, "h": 8 }, "duration": 100 }
+        ],
+        "meta": {
+            "image": "w.png",
+            "size": { "w": 16, "h": 8 },
+            "frameTags": [{ "name": "idle", "from": 0, "to": 2 }]
+        }
+    }"#;
+    let path = Path::new("w.json");
+    let Err(LoadError::InvalidFrameRange {
+        animation, last, ..
+    }) = AsepriteSheet::from_bytes(path, json.as_bytes())
+    else {
+        panic!("tag past the last frame is not reported");
+    };
+    assert_eq!((animation.as_str(), last), ("idle", 2));
+    let valid = json.replace(r#""to": 2"#, r#""to": 1"#);
+    assert!(AsepriteSheet::from_bytes(path, valid.as_bytes()).is_ok());
+}
@@ -1,51 +1,82 @@
-use std::{borrow::Cow, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
     prelude::{Handle, Image, Resource},
+    reflect::{TypePath, TypeUuid},
     sprite::TextureAtlas,
-    utils::HashMap,
+    utils::{BoxedFuture, HashMap},
 };
 use serde::Deserialize;
 
-use super::common::{Position, Rect, Size};
+use crate::components::animation::DEFAULT_ANIMATION;
+
+use super::{
+    aseprite::AsepriteSheet,
+    common::{Position, Rect, Size},
+    error::{LoadError, LoaderErrors},
+    inheritance::{EntityTypeBuilder, EntityTypeTemplate},
+};
 
-#[derive(Resource)]
+#[derive(Resource, Default)]
 pub struct EntityTypes {
+    /// Handles of the entity type assets by entity type name
+    pub handles: HashMap<String, Handle<EntityType>>,
+    /// Entity types copied from the loaded assets. This is where the
+    /// `loaded` field is filled in once the textures are available.
     pub map: HashMap<String, EntityType>,
+    /// Handles of the templates, which keep them loaded so their
+    /// modifications are noticed
+    pub templates: Vec<Handle<EntityTypeTemplate>>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, TypeUuid, TypePath)]
+#[uuid = "e280346d-4b8b-46af-8ccf-9cf727856d3e"]
 pub struct EntityType {
     #[serde(flatten)]
     pub size: Size,
-    pub collision: Option<Rect>,
+    pub collision: Option<CollisionShapes>,
+    /// Layer of the collision shapes
+    #[serde(default)]
+    pub collision_layer: CollisionLayer,
+    /// Layers the collision shapes collide with. All layers are used
+    /// if no mask is given.
+    pub collision_mask: Option<Vec<CollisionLayer>>,
     pub interaction: Option<Interaction>,
     #[serde(flatten)]
     pub image: EntityImage,
     #[serde(skip)]
     pub loaded: Option<Loaded>,
+    /// Templates and entity types this entity type extends as paths
+    /// inside the asset folder. The entity type is reloaded when one of
+    /// them is modified.
+    #[serde(skip)]
+    pub parents: Vec<PathBuf>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Loaded {
     Static(Handle<Image>),
-    //Animation(LoadedAnimation),
+    Animation(LoadedAnimation),
     Animations(LoadedAnimations),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LoadedAnimation {
     pub atlas: Handle<TextureAtlas>,
     pub frames: Vec<(usize, Duration)>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LoadedAnimations {
     pub atlas: Handle<TextureAtlas>,
     pub frames: HashMap<String, Vec<(usize, Duration)>>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub enum EntityImage {
     #[serde(rename = "image")]
     Static(String),
@@ -53,11 +84,125 @@ pub enum EntityImage {
     Animation(Frames),
     #[serde(rename = "animations")]
     Animations(HashMap<String, Frames>),
+    #[serde(rename = "sprite_sheet")]
+    SpriteSheet(SpriteSheet),
+    #[serde(rename = "aseprite")]
+    Aseprite(AsepriteImage),
+}
+
+impl EntityImage {
+    /// Paths of all images inside the asset folder
+    pub fn image_paths(&self) -> Vec<PathBuf> {
+        let entities = Path::new("entities");
+        match self {
+            Self::Static(image) => vec![entities.join(image)],
+            Self::Animation(frames) => frames
+                .iter()
+                .map(|frame| entities.join(&frame.image))
+                .collect(),
+            Self::Animations(animations) => animations
+                .values()
+                .flatten()
+                .map(|frame| entities.join(&frame.image))
+                .collect(),
+            Self::SpriteSheet(sprite_sheet) => vec![entities.join(&sprite_sheet.image)],
+            Self::Aseprite(aseprite) => aseprite.image_path().into_iter().collect(),
+        }
+    }
+    /// Names of the animations which can be started on entities of
+    /// this type
+    pub fn animation_names(&self) -> Vec<&str> {
+        match self {
+            Self::Static(_) => Vec::new(),
+            Self::Animation(_) => vec![DEFAULT_ANIMATION],
+            Self::Animations(animations) => animations.keys().map(String::as_str).collect(),
+            Self::SpriteSheet(sprite_sheet) => {
+                sprite_sheet.animations.keys().map(String::as_str).collect()
+            }
+            Self::Aseprite(aseprite) => match &aseprite.sheet {
+                Some(sheet) if !sheet.meta.frame_tags.is_empty() => sheet
+                    .meta
+                    .frame_tags
+                    .iter()
+                    .map(|tag| tag.name.as_str())
+                    .collect(),
+                _ => vec![DEFAULT_ANIMATION],
+            },
+        }
+    }
+    /// Animation started on entities which don't select one. This is
+    /// the `default` animation if it exists and the first animation in
+    /// alphabetical order otherwise. `None` is returned if there are no
+    /// animations.
+    pub fn initial_animation(&self) -> Option<&str> {
+        let animation_names = self.animation_names();
+        if animation_names.contains(&DEFAULT_ANIMATION) {
+            Some(DEFAULT_ANIMATION)
+        } else {
+            animation_names.into_iter().min()
+        }
+    }
+}
+
+/// A single image containing all animation frames in a grid of
+/// equally sized tiles. Tiles are numbered row by row starting with
+/// 0 in the upper left corner.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SpriteSheet {
+    pub image: String,
+    pub tile: Size,
+    pub columns: usize,
+    pub rows: usize,
+    /// Space between the tiles
+    pub padding: Option<Size>,
+    /// Position of the first tile in the image
+    pub offset: Option<Position>,
+    pub animations: HashMap<String, SpriteSheetAnimation>,
+}
+
+/// Sprite sheet exported by Aseprite. Only the path of the JSON file
+/// is part of the entity type file. The JSON file is read by the
+/// `EntityTypeLoader` and provides the image, frames, durations and
+/// animation names (tags).
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "String")]
+pub struct AsepriteImage {
+    pub file: String,
+    pub sheet: Option<AsepriteSheet>,
+}
+
+impl From<String> for AsepriteImage {
+    fn from(file: String) -> Self {
+        Self { file, sheet: None }
+    }
+}
+
+impl AsepriteImage {
+    /// Path of the JSON file inside the asset folder
+    pub fn json_path(&self) -> PathBuf {
+        Path::new("entities").join(&self.file)
+    }
+    /// Path of the sprite sheet image inside the asset folder. The
+    /// JSON file references the image relative to itself.
+    pub fn image_path(&self) -> Option<PathBuf> {
+        let sheet = self.sheet.as_ref()?;
+        let json_path = self.json_path();
+        Some(json_path.parent()?.join(&sheet.meta.image))
+    }
+}
+
+/// Animation playing the tiles `first` to `last` (inclusive) of a
+/// sprite sheet
+#[derive(Deserialize, Debug, Clone)]
+pub struct SpriteSheetAnimation {
+    pub first: usize,
+    pub last: usize,
+    pub duration: u64,
 }
 
 pub type Frames = Vec<Frame>;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Frame {
     pub image: String,
     pub duration: u64,
@@ -65,36 +210,240 @@ pub struct Frame {
     pub index: usize,
 }
 
-#[derive(Deserialize, Debug)]
+/// Collision of an entity type. Like the collision box the shapes use
+/// pixel coordinates of the entity image with 0:0 in the upper left
+/// corner. A single box can be given without a list for compatibility.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(from = "CollisionData")]
+pub struct CollisionShapes(pub Vec<CollisionShape>);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CollisionData {
+    Box(Rect),
+    Shapes(Vec<CollisionShape>),
+}
+
+impl From<CollisionData> for CollisionShapes {
+    fn from(data: CollisionData) -> Self {
+        match data {
+            CollisionData::Box(rect) => Self(vec![CollisionShape::Box(rect)]),
+            CollisionData::Shapes(shapes) => Self(shapes),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "shape", rename_all = "snake_case")]
+pub enum CollisionShape {
+    Box(Rect),
+    Circle {
+        #[serde(flatten)]
+        center: Position,
+        radius: u16,
+    },
+    /// Convex polygon
+    Polygon {
+        points: Vec<Position>,
+    },
+}
+
+impl CollisionShape {
+    /// Bounding box as upper left and lower right corner
+    pub fn bounds(&self) -> ((i32, i32), (i32, i32)) {
+        match self {
+            Self::Box(rect) => {
+                let (x, y) = (i32::from(rect.position.x), i32::from(rect.position.y));
+                (
+                    (x, y),
+                    (
+                        x + i32::from(rect.size.width),
+                        y + i32::from(rect.size.height),
+                    ),
+                )
+            }
+            Self::Circle { center, radius } => {
+                let (x, y, radius) = (i32::from(center.x), i32::from(center.y), i32::from(*radius));
+                ((x - radius, y - radius), (x + radius, y + radius))
+            }
+            Self::Polygon { points } => points.iter().fold(
+                ((i32::MAX, i32::MAX), (i32::MIN, i32::MIN)),
+                |((min_x, min_y), (max_x, max_y)), point| {
+                    let (x, y) = (i32::from(point.x), i32::from(point.y));
+                    ((min_x.min(x), min_y.min(y)), (max_x.max(x), max_y.max(y)))
+                },
+            ),
+        }
+    }
+
+    /// Check if a polygon has at least 3 points and is convex. Other
+    /// shapes are always valid.
+    pub fn is_valid(&self) -> bool {
+        let Self::Polygon { points } = self else {
+            return true;
+        };
+        if points.len() < 3 {
+            return false;
+        }
+        // All turns along the outline have to go into the same direction
+        let mut sign = 0;
+        for i in 0..points.len() {
+            let [a, b, c] = [0, 1, 2].map(|offset| points[(i + offset) % points.len()]);
+            let cross = (i32::from(b.x) - i32::from(a.x)) * (i32::from(c.y) - i32::from(b.y))
+                - (i32::from(b.y) - i32::from(a.y)) * (i32::from(c.x) - i32::from(b.x));
+            match (sign, cross.signum()) {
+                (_, 0) => {}
+                (0, turn) => sign = turn,
+                (sign, turn) if sign != turn => return false,
+                _ => {}
+            }
+        }
+        sign != 0
+    }
+}
+
+/// Kinds of colliders. Two colliders only collide if the collision
+/// mask of both contains the layer of the other one.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionLayer {
+    Player,
+    Npc,
+    Item,
+    Projectile,
+    /// Static objects placed in the world
+    #[default]
+    Prop,
+}
+
+impl CollisionLayer {
+    pub const ALL: [Self; 5] = [
+        Self::Player,
+        Self::Npc,
+        Self::Item,
+        Self::Projectile,
+        Self::Prop,
+    ];
+
+    pub fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    /// Combine layers to a collision mask
+    pub fn mask(layers: &[Self]) -> u32 {
+        layers.iter().fold(0, |mask, layer| mask | layer.bit())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct Interaction {
     pub name: String,
     pub position: Position,
     pub max_distance: u16,
 }
 
-pub fn load_entity_types() -> Result<EntityTypes, anyhow::Error> {
-    let mut entity_types: HashMap<String, EntityType> = HashMap::default();
-    let dir = "assets/entity_types";
-    for entry in std::fs::read_dir(dir)
-        .unwrap_or_else(|e| panic!("Reading directory {:?} failed: {:?}", dir, e))
-    {
-        let entry = entry?;
-        if !entry.metadata()?.is_file() {
-            // Skip non-regular files
-            continue;
+/// Directory inside the asset folder containing the entity types
+pub const ENTITY_TYPES_DIR: &str = "entity_types";
+
+/// File extension of entity type files
+pub const ENTITY_TYPE_EXTENSION: &str = "entity.yaml";
+
+/// Get the entity type name from the path of an entity type file.
+/// The name is the file name without any extensions, e.g.
+/// `entity_types/wolfgang.entity.yaml` becomes `wolfgang`.
+pub fn entity_type_name(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_string_lossy();
+    file_name.split('.').next().map(String::from)
+}
+
+/// Loads entity types together with the templates and entity types
+/// they extend. Errors are also stored in the `LoaderErrors`.
+#[derive(Default)]
+pub struct EntityTypeLoader {
+    pub errors: LoaderErrors,
+}
+
+impl AssetLoader for EntityTypeLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            load_entity_type(bytes, load_context)
+                .await
+                .map_err(|error| self.errors.report(error))
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[ENTITY_TYPE_EXTENSION]
+    }
+}
+
+async fn load_entity_type(
+    bytes: &[u8],
+    load_context: &mut LoadContext<'_>,
+) -> Result<(), LoadError> {
+    let mut builder = EntityTypeBuilder::new(load_context.path(), bytes)?;
+    while let Some(parent) = builder.parent()? {
+        let mut found = false;
+        for path in EntityTypeBuilder::parent_paths(&parent) {
+            if let Ok(bytes) = load_context.read_asset_bytes(&path).await {
+                builder.add_parent(&path, &bytes)?;
+                found = true;
+                break;
+            }
         }
-        let path = entry.path();
-        let ext = path.extension().map(|ext| ext.to_string_lossy());
-        if ext != Some(Cow::Borrowed("yaml")) {
-            // Skip non-yaml files
-            continue;
+        if !found {
+            return Err(builder.unknown_parent(&parent));
         }
-        let file = std::fs::File::open(path.clone())
-            .unwrap_or_else(|e| panic!("Reading {:?} failed: {:?}", path, e));
-        let entity_type: EntityType = serde_yaml::from_reader(file)
-            .unwrap_or_else(|e| panic!("Parsing {:?} failed: {:?}", path, e));
-        let entity_name = path.file_stem().unwrap().to_string_lossy();
-        entity_types.insert(entity_name.to_string(), entity_type);
     }
-    Ok(EntityTypes { map: entity_types })
+    let mut entity_type = builder.build()?;
+    let mut dependencies = Vec::new();
+    if let EntityImage::Aseprite(aseprite) = &mut entity_type.image {
+        let json_path = aseprite.json_path();
+        let json = load_context
+            .read_asset_bytes(&json_path)
+            .await
+            .map_err(|e| LoadError::asset_io(&json_path, e))?;
+        aseprite.sheet = Some(AsepriteSheet::from_bytes(&json_path, &json)?);
+        // Modifying the exported JSON reloads the entity type as well
+        dependencies.push(AssetPath::from(json_path));
+    }
+    load_context.set_default_asset(LoadedAsset::new(entity_type).with_dependencies(dependencies));
+    Ok(())
+}
+
+#[test]
+fn test_initial_animation() {
+    let entity_type: EntityType = serde_yaml::from_str(
+        "
+width: 8
+height: 8
+animations:
+  walk:
+    - image: walk.png
+      duration: 100
+  idle:
+    - image: idle.png
+      duration: 100
+",
+    )
+    .unwrap();
+    assert_eq!(entity_type.image.initial_animation(), Some("idle"));
+    let entity_type: EntityType = serde_yaml::from_str(
+        "
+width: 8
+height: 8
+animation:
+  - image: idle.png
+    duration: 100
+",
+    )
+    .unwrap();
+    assert_eq!(
+        entity_type.image.initial_animation(),
+        Some(DEFAULT_ANIMATION)
+    );
 }
@@ -0,0 +1,356 @@
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use bevy::{asset::AssetIoError, prelude::Resource};
+
+/// Problems found while loading the game data. Errors are collected
+/// in `LoadErrors` so all of them can be reported at once.
+#[derive(Debug)]
+pub enum LoadError {
+    MissingFile {
+        path: PathBuf,
+    },
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    /// The file could not be parsed. `line` and `column` start at 1.
+    Syntax {
+        path: PathBuf,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+    Image {
+        path: PathBuf,
+        message: String,
+    },
+    UnknownEntityType {
+        path: PathBuf,
+        entity: String,
+        entity_type: String,
+    },
+    MissingImage {
+        path: PathBuf,
+        image: PathBuf,
+    },
+    UnknownAnimation {
+        path: PathBuf,
+        animation: String,
+    },
+    /// The `extends` chain of an entity type contains a cycle
+    InheritanceCycle {
+        path: PathBuf,
+        chain: Vec<String>,
+    },
+    /// Neither a template nor an entity type with the name given in
+    /// `extends` exists
+    UnknownParent {
+        path: PathBuf,
+        parent: String,
+    },
+    /// A collision shape of an entity type exceeds the entity size
+    CollisionOutOfBounds {
+        path: PathBuf,
+    },
+    /// A collision polygon has less than 3 points or is concave
+    InvalidCollisionPolygon {
+        path: PathBuf,
+    },
+    /// The interaction position of an entity type is outside of the
+    /// entity size
+    InteractionOutOfBounds {
+        path: PathBuf,
+    },
+    /// An animation plays no frames or frames which don't exist.
+    /// `first` and `last` are inclusive.
+    InvalidFrameRange {
+        path: PathBuf,
+        animation: String,
+        first: usize,
+        last: usize,
+        frame_count: usize,
+    },
+    /// The map file selects a level which does not exist in the LDtk
+    /// project
+    UnknownLevel {
+        path: PathBuf,
+        level: String,
+    },
+    /// Two entities of an imported map have the same name
+    DuplicateEntity {
+        path: PathBuf,
+        entity: String,
+    },
+    /// A door leads to a map which is not in the map registry
+    UnknownMap {
+        path: PathBuf,
+        map: String,
+    },
+    /// A door leads to a spawn point which does not exist on its map
+    UnknownSpawnPoint {
+        path: PathBuf,
+        map: String,
+        spawn_point: String,
+    },
+    /// An entity or the player is placed on blocked terrain
+    BlockedSpawnPoint {
+        path: PathBuf,
+        entity: String,
+        x: f32,
+        y: f32,
+    },
+    /// A baked navigation mesh was baked with another version, for
+    /// another agent radius or from a different collision
+    OutdatedNavMesh {
+        path: PathBuf,
+    },
+}
+
+impl LoadError {
+    pub fn io(path: &Path, error: io::Error) -> Self {
+        if error.kind() == io::ErrorKind::NotFound {
+            Self::MissingFile {
+                path: path.to_owned(),
+            }
+        } else {
+            Self::Io {
+                path: path.to_owned(),
+                error,
+            }
+        }
+    }
+    /// Error of reading a file through the `AssetServer`
+    pub fn asset_io(path: &Path, error: AssetIoError) -> Self {
+        match error {
+            AssetIoError::NotFound(path) => Self::MissingFile { path },
+            AssetIoError::Io(error) => Self::io(path, error),
+            error => Self::Io {
+                path: path.to_owned(),
+                error: io::Error::other(error),
+            },
+        }
+    }
+    /// Error without location for files which are syntactically valid
+    /// but can't be interpreted
+    pub fn syntax(path: &Path, message: impl Into<String>) -> Self {
+        Self::Syntax {
+            path: path.to_owned(),
+            line: None,
+            column: None,
+            message: message.into(),
+        }
+    }
+    pub fn yaml(path: &Path, error: serde_yaml::Error) -> Self {
+        let location = error.location();
+        Self::Syntax {
+            path: path.to_owned(),
+            line: location.as_ref().map(|l| l.line()),
+            column: location.as_ref().map(|l| l.column()),
+            message: error.to_string(),
+        }
+    }
+    pub fn json(path: &Path, error: serde_json::Error) -> Self {
+        Self::Syntax {
+            path: path.to_owned(),
+            line: Some(error.line()),
+            column: Some(error.column()),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingFile { path } => write!(f, "{}: file not found", path.display()),
+            Self::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            Self::Syntax {
+                path,
+                line,
+                column,
+                message,
+            } => {
+                write!(f, "{}", path.display())?;
+                if let Some(line) = line {
+                    write!(f, ":{}", line)?;
+                }
+                if let Some(column) = column {
+                    write!(f, ":{}", column)?;
+                }
+                write!(f, ": {}", message)
+            }
+            Self::Image { path, message } => write!(f, "{}: {}", path.display(), message),
+            Self::UnknownEntityType {
+                path,
+                entity,
+                entity_type,
+            } => write!(
+                f,
+                "{}: entity {:?} references unknown entity type {:?}",
+                path.display(),
+                entity,
+                entity_type
+            ),
+            Self::MissingImage { path, image } => write!(
+                f,
+                "{}: image {} does not exist",
+                path.display(),
+                image.display()
+            ),
+            Self::UnknownAnimation { path, animation } => write!(
+                f,
+                "{}: animation {:?} does not exist",
+                path.display(),
+                animation
+            ),
+            Self::InheritanceCycle { path, chain } => write!(
+                f,
+                "{}: entity type inheritance contains a cycle: {}",
+                path.display(),
+                chain.join(" -> ")
+            ),
+            Self::UnknownParent { path, parent } => write!(
+                f,
+                "{}: extended template or entity type {:?} does not exist",
+                path.display(),
+                parent
+            ),
+            Self::CollisionOutOfBounds { path } => write!(
+                f,
+                "{}: collision shape exceeds the entity size",
+                path.display()
+            ),
+            Self::InvalidCollisionPolygon { path } => write!(
+                f,
+                "{}: collision polygons need at least 3 points and must be convex",
+                path.display()
+            ),
+            Self::InteractionOutOfBounds { path } => write!(
+                f,
+                "{}: interaction position is outside of the entity size",
+                path.display()
+            ),
+            Self::InvalidFrameRange {
+                path,
+                animation,
+                first,
+                last,
+                frame_count,
+            } => write!(
+                f,
+                "{}: animation {:?} plays frames {} to {}, which is empty or exceeds the {} frames",
+                path.display(),
+                animation,
+                first,
+                last,
+                frame_count
+            ),
+            Self::UnknownLevel { path, level } => {
+                write!(f, "{}: level {:?} does not exist", path.display(), level)
+            }
+            Self::DuplicateEntity { path, entity } => write!(
+                f,
+                "{}: entity name {:?} is used more than once",
+                path.display(),
+                entity
+            ),
+            Self::UnknownMap { path, map } => {
+                write!(f, "{}: door leads to unknown map {:?}", path.display(), map)
+            }
+            Self::UnknownSpawnPoint {
+                path,
+                map,
+                spawn_point,
+            } => write!(
+                f,
+                "{}: door leads to unknown spawn point {:?} of map {:?}",
+                path.display(),
+                spawn_point,
+                map
+            ),
+            Self::BlockedSpawnPoint { path, entity, x, y } => write!(
+                f,
+                "{}: entity {:?} is placed on blocked terrain at {}:{}",
+                path.display(),
+                entity,
+                x,
+                y
+            ),
+            Self::OutdatedNavMesh { path } => write!(
+                f,
+                "{}: navigation mesh is outdated, bake it again with `make nav-meshes`",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Errors found while loading the game data. The resource is inserted
+/// before the app exits because of them.
+#[derive(Resource, Debug, Default)]
+pub struct LoadErrors {
+    pub errors: Vec<LoadError>,
+}
+
+impl LoadErrors {
+    pub fn push(&mut self, error: LoadError) {
+        self.errors.push(error);
+    }
+    /// Collect the error of a result and return the value if there was
+    /// no error.
+    pub fn collect<T>(&mut self, result: Result<T, LoadError>) -> Option<T> {
+        result.map_err(|error| self.push(error)).ok()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+    pub fn into_result<T>(self, value: T) -> Result<T, LoadErrors> {
+        if self.is_empty() {
+            Ok(value)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl fmt::Display for LoadErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Loading the game data failed with {} error(s):",
+            self.errors.len()
+        )?;
+        for error in self.errors.iter() {
+            writeln!(f, "  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LoadErrors {}
+
+/// Errors of the asset loaders. The `AssetServer` only logs why an
+/// asset failed to load, so the loaders store their errors here as well
+/// and the errors are reported together with the checks of the data.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct LoaderErrors(Arc<Mutex<Vec<LoadError>>>);
+
+impl LoaderErrors {
+    /// Store an error and convert it to the error returned by the
+    /// loader
+    pub fn report(&self, error: LoadError) -> anyhow::Error {
+        let message = anyhow::anyhow!("{}", error);
+        self.0.lock().unwrap().push(error);
+        message
+    }
+    /// Remove all errors stored so far
+    pub fn take(&self) -> Vec<LoadError> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
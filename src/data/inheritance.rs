@@ -0,0 +1,271 @@
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    reflect::{TypePath, TypeUuid},
+    utils::BoxedFuture,
+};
+use serde_yaml::{Mapping, Value};
+
+use super::{
+    entity_types::{entity_type_name, EntityType, ENTITY_TYPES_DIR, ENTITY_TYPE_EXTENSION},
+    error::{LoadError, LoaderErrors},
+};
+
+/// File extension of entity type templates. Templates can be extended
+/// by entity types but are not entity types on their own. This is why
+/// they don't need to contain all required fields.
+pub const TEMPLATE_EXTENSION: &str = "template.yaml";
+
+/// Keys selecting the `EntityImage` variant. Only one of them may be
+/// present in the merged entity type.
+const IMAGE_KEYS: [&str; 5] = [
+    "image",
+    "animation",
+    "animations",
+    "sprite_sheet",
+    "aseprite",
+];
+
+/// Placeholder in interaction names which is replaced by the name of
+/// the entity type
+const NAME_PLACEHOLDER: &str = "{name}";
+
+/// Builds an entity type from a file which may `extend` other entity
+/// types or templates. The caller is responsible for reading the files
+/// returned by `parent` and passing them to `add_parent` until no
+/// parent is left:
+///
+/// ```ignore
+/// let mut builder = EntityTypeBuilder::new(path, bytes)?;
+/// while let Some(parent) = builder.parent()? {
+///     let (parent_path, parent_bytes) = read(&parent)?;
+///     builder.add_parent(&parent_path, &parent_bytes)?;
+/// }
+/// let entity_type = builder.build()?;
+/// ```
+pub struct EntityTypeBuilder {
+    path: PathBuf,
+    name: String,
+    /// File names of the entity type followed by its parents
+    chain: Vec<(String, Mapping)>,
+    /// Paths of the parents added so far
+    parents: Vec<PathBuf>,
+}
+
+impl EntityTypeBuilder {
+    pub fn new(path: &Path, bytes: &[u8]) -> Result<Self, LoadError> {
+        let mapping = parse(path, bytes)?;
+        Ok(Self {
+            path: path.to_owned(),
+            name: entity_type_name(path).unwrap_or_default(),
+            chain: vec![(file_name(path), mapping)],
+            parents: Vec::new(),
+        })
+    }
+    /// Name of the parent which needs to be added next
+    pub fn parent(&self) -> Result<Option<String>, LoadError> {
+        let (_, last) = self.chain.last().unwrap();
+        let Some(parent) = last.get("extends") else {
+            return Ok(None);
+        };
+        let Some(parent) = parent.as_str() else {
+            return Err(LoadError::Syntax {
+                path: self.path.clone(),
+                line: None,
+                column: None,
+                message: "extends: expected the name of an entity type or template".into(),
+            });
+        };
+        Ok(Some(parent.to_owned()))
+    }
+    /// Paths inside the asset folder where the given parent is looked
+    /// up. Templates take precedence over entity types.
+    pub fn parent_paths(parent: &str) -> [PathBuf; 2] {
+        let dir = Path::new(ENTITY_TYPES_DIR);
+        [
+            dir.join(format!("{}.{}", parent, TEMPLATE_EXTENSION)),
+            dir.join(format!("{}.{}", parent, ENTITY_TYPE_EXTENSION)),
+        ]
+    }
+    /// Add the parent read from one of the `parent_paths`. An error is
+    /// returned if the file is already part of the inheritance chain.
+    /// Files are compared instead of names, as an entity type may
+    /// extend a template with the same name.
+    pub fn add_parent(&mut self, path: &Path, bytes: &[u8]) -> Result<(), LoadError> {
+        if path == self.path || self.parents.iter().any(|parent| parent == path) {
+            let mut chain = self
+                .chain
+                .iter()
+                .map(|(file_name, _)| file_name.clone())
+                .collect::<Vec<_>>();
+            chain.push(file_name(path));
+            return Err(LoadError::InheritanceCycle {
+                path: self.path.clone(),
+                chain,
+            });
+        }
+        let mapping = parse(path, bytes)?;
+        self.chain.push((file_name(path), mapping));
+        self.parents.push(path.to_owned());
+        Ok(())
+    }
+    /// Error for a parent that could not be found at any of the
+    /// `parent_paths`
+    pub fn unknown_parent(&self, parent: &str) -> LoadError {
+        LoadError::UnknownParent {
+            path: self.path.clone(),
+            parent: parent.to_owned(),
+        }
+    }
+    pub fn build(self) -> Result<EntityType, LoadError> {
+        let merged = self
+            .chain
+            .into_iter()
+            .rev()
+            .map(|(_, mapping)| mapping)
+            .reduce(merge_entity_type)
+            .unwrap();
+        let mut entity_type: EntityType = serde_yaml::from_value(Value::Mapping(merged))
+            .map_err(|e| LoadError::yaml(&self.path, e))?;
+        if let Some(interaction) = &mut entity_type.interaction {
+            interaction.name = interaction.name.replace(NAME_PLACEHOLDER, &self.name);
+        }
+        entity_type.parents = self.parents;
+        Ok(entity_type)
+    }
+}
+
+/// Template which can be extended by entity types. Templates are
+/// loaded as assets on their own, so the entity types extending them
+/// can be reloaded when they are modified.
+#[derive(Debug, Clone, TypeUuid, TypePath)]
+#[uuid = "5b0f6a53-3c1e-4c2b-9d43-8f0e2f4f6a1d"]
+pub struct EntityTypeTemplate(pub Mapping);
+
+#[derive(Default)]
+pub struct EntityTypeTemplateLoader {
+    pub errors: LoaderErrors,
+}
+
+impl AssetLoader for EntityTypeTemplateLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let mapping =
+                parse(load_context.path(), bytes).map_err(|error| self.errors.report(error))?;
+            load_context.set_default_asset(LoadedAsset::new(EntityTypeTemplate(mapping)));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[TEMPLATE_EXTENSION]
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn parse(path: &Path, bytes: &[u8]) -> Result<Mapping, LoadError> {
+    serde_yaml::from_slice(bytes).map_err(|e| LoadError::yaml(path, e))
+}
+
+/// Merge an entity type into its parent. An image of the child replaces
+/// the image of the parent even if a different kind of image is used.
+fn merge_entity_type(mut parent: Mapping, mut child: Mapping) -> Mapping {
+    child.remove("extends");
+    parent.remove("extends");
+    if IMAGE_KEYS.iter().any(|key| child.contains_key(*key)) {
+        for key in IMAGE_KEYS {
+            parent.remove(key);
+        }
+    }
+    merge(parent, child)
+}
+
+/// Deep merge two mappings. Values of the child take precedence.
+fn merge(mut parent: Mapping, child: Mapping) -> Mapping {
+    for (key, value) in child {
+        let value = match (parent.remove(&key), value) {
+            (Some(Value::Mapping(parent_value)), Value::Mapping(value)) => {
+                Value::Mapping(merge(parent_value, value))
+            }
+            (_, value) => value,
+        };
+        parent.insert(key, value);
+    }
+    parent
+}
+
+#[test]
+fn test_inheritance() {
+    let template = b"
+interaction:
+  name: mine_{name}
+  max_distance: 120
+image: base.png
+";
+    let child = b"
+extends: crystals
+width: 100
+height: 50
+interaction:
+  position:
+    x: 10
+    y: 20
+animation:
+  - image: a.png
+    duration: 100
+";
+    let mut builder =
+        EntityTypeBuilder::new(Path::new("crystals_small.entity.yaml"), child).unwrap();
+    assert_eq!(builder.parent().unwrap().as_deref(), Some("crystals"));
+    builder
+        .add_parent(Path::new("crystals.template.yaml"), template)
+        .unwrap();
+    assert_eq!(builder.parent().unwrap(), None);
+    let entity_type = builder.build().unwrap();
+    let interaction = entity_type.interaction.unwrap();
+    assert_eq!(interaction.name, "mine_crystals_small");
+    assert_eq!(interaction.max_distance, 120);
+    assert_eq!(interaction.position.x, 10);
+    assert!(matches!(
+        entity_type.image,
+        super::entity_types::EntityImage::Animation(_)
+    ));
+    assert_eq!(
+        entity_type.parents,
+        [PathBuf::from("crystals.template.yaml")]
+    );
+}
+
+#[test]
+fn test_inheritance_cycle() {
+    let mut builder = EntityTypeBuilder::new(Path::new("a.entity.yaml"), b"extends: b").unwrap();
+    assert_eq!(builder.parent().unwrap().as_deref(), Some("b"));
+    builder
+        .add_parent(Path::new("b.template.yaml"), b"extends: a")
+        .unwrap();
+    assert_eq!(builder.parent().unwrap().as_deref(), Some("a"));
+    assert!(matches!(
+        builder.add_parent(Path::new("a.entity.yaml"), b"extends: b"),
+        Err(LoadError::InheritanceCycle { .. })
+    ));
+
+    // An entity type may extend the template with its own name
+    let mut builder =
+        EntityTypeBuilder::new(Path::new("crystals.entity.yaml"), b"extends: crystals").unwrap();
+    assert_eq!(builder.parent().unwrap().as_deref(), Some("crystals"));
+    builder
+        .add_parent(Path::new("crystals.template.yaml"), b"image: base.png")
+        .unwrap();
+    assert_eq!(builder.parent().unwrap(), None);
+}
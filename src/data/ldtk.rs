@@ -0,0 +1,259 @@
+//! Import of LDtk projects. Every level of a project is a separate map
+//! which is selected by its identifier, e.g. `map/world.ldtk#Level_1`.
+//! Without identifier the first level is used.
+//!
+//! - Entity instances of all layers become map entities. The entity
+//!   identifier is used as entity type and the field values become the
+//!   instance properties.
+//! - The IntGrid layer named `collision` defines the collision mask.
+//!   Every cell with a value other than 0 is blocked.
+//! - The background image of the level is used as background image.
+//!
+//! LDtk capitalizes identifiers by default, this is why identifiers are
+//! converted to lower case. The origin of LDtk levels is the upper left
+//! corner while the game uses the center of the level.
+
+use std::path::Path;
+
+use image::{GrayImage, Luma};
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use super::{
+    error::LoadError,
+    map::{
+        Map, MapBounds, MapEntities, MapEntity, MapSpawnPoints, MapTriggers, BACKGROUND_FILE,
+        COLLISION_LAYER,
+    },
+};
+
+/// Check if the file is an LDtk project by its extension
+pub fn is_ldtk_project(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "ldtk")
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkProject {
+    /// Levels are saved in separate files which is not supported
+    #[serde(default)]
+    pub external_levels: bool,
+    pub levels: Vec<LdtkLevel>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkLevel {
+    pub identifier: String,
+    pub px_wid: u32,
+    pub px_hei: u32,
+    /// Background image relative to the project file
+    pub bg_rel_path: Option<String>,
+    pub layer_instances: Option<Vec<LdtkLayer>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LdtkLayer {
+    #[serde(rename = "__identifier")]
+    pub identifier: String,
+    #[serde(rename = "__type")]
+    pub layer_type: String,
+    /// Width in cells
+    #[serde(rename = "__cWid")]
+    pub columns: u32,
+    /// Height in cells
+    #[serde(rename = "__cHei")]
+    pub rows: u32,
+    #[serde(rename = "__gridSize")]
+    pub grid_size: u32,
+    #[serde(rename = "__pxTotalOffsetX")]
+    pub offset_x: i32,
+    #[serde(rename = "__pxTotalOffsetY")]
+    pub offset_y: i32,
+    #[serde(rename = "intGridCsv", default)]
+    pub int_grid: Vec<u32>,
+    #[serde(rename = "entityInstances", default)]
+    pub entities: Vec<LdtkEntity>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LdtkEntity {
+    #[serde(rename = "__identifier")]
+    pub identifier: String,
+    /// Relative position of `px` inside the entity
+    #[serde(rename = "__pivot")]
+    pub pivot: [f64; 2],
+    /// Unique id which is used as entity name
+    pub iid: String,
+    pub width: u32,
+    pub height: u32,
+    /// Position of the pivot relative to the layer
+    pub px: [i32; 2],
+    #[serde(rename = "fieldInstances", default)]
+    pub fields: Vec<LdtkField>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LdtkField {
+    #[serde(rename = "__identifier")]
+    pub identifier: String,
+    #[serde(rename = "__value")]
+    pub value: Value,
+}
+
+impl LdtkProject {
+    pub fn from_bytes(path: &Path, bytes: &[u8]) -> Result<Self, LoadError> {
+        let project: Self = serde_json::from_slice(bytes).map_err(|e| LoadError::json(path, e))?;
+        if project.external_levels {
+            return Err(LoadError::syntax(
+                path,
+                "levels saved in separate files are not supported",
+            ));
+        }
+        Ok(project)
+    }
+
+    /// Get the level with the given identifier or the first level
+    pub fn level(&self, path: &Path, identifier: Option<&str>) -> Result<&LdtkLevel, LoadError> {
+        match identifier {
+            Some(identifier) => self
+                .levels
+                .iter()
+                .find(|level| level.identifier == identifier)
+                .ok_or_else(|| LoadError::UnknownLevel {
+                    path: path.to_owned(),
+                    level: identifier.to_owned(),
+                }),
+            None => self
+                .levels
+                .first()
+                .ok_or_else(|| LoadError::syntax(path, "project contains no levels")),
+        }
+    }
+}
+
+impl LdtkLevel {
+    fn layers(&self) -> &[LdtkLayer] {
+        self.layer_instances.as_deref().unwrap_or_default()
+    }
+
+    pub fn to_map(&self, path: &Path) -> Result<Map, LoadError> {
+        let mut entities = MapEntities::default();
+        for layer in self.layers() {
+            for entity in layer.entities.iter() {
+                let (width, height) = (f64::from(entity.width), f64::from(entity.height));
+                let x = f64::from(entity.px[0] + layer.offset_x) + (0.5 - entity.pivot[0]) * width;
+                let y = f64::from(entity.px[1] + layer.offset_y) + (0.5 - entity.pivot[1]) * height;
+                let x = x - f64::from(self.px_wid) / 2.0;
+                let y = f64::from(self.px_hei) / 2.0 - y;
+                // Unset optional fields are null which is not a valid
+                // value for the entity overrides
+                let properties = entity
+                    .fields
+                    .iter()
+                    .filter(|field| !field.value.is_null())
+                    .map(|field| (field.identifier.to_lowercase(), field.value.clone()));
+                let map_entity =
+                    MapEntity::from_properties(entity.identifier.to_lowercase(), x, y, properties)
+                        .map_err(|e| {
+                            LoadError::syntax(path, format!("entity {}: {}", entity.iid, e))
+                        })?;
+                entities.insert(entity.iid.clone(), map_entity);
+            }
+        }
+        Ok(Map {
+            entities,
+            triggers: MapTriggers::default(),
+            spawn_points: MapSpawnPoints::default(),
+            bounds: Some(MapBounds {
+                x: 0,
+                y: 0,
+                width: self.px_wid,
+                height: self.px_hei,
+            }),
+            background: self
+                .bg_rel_path
+                .clone()
+                .unwrap_or_else(|| BACKGROUND_FILE.to_owned()),
+            collision_mask: self.collision_mask(),
+        })
+    }
+
+    /// Create the collision mask from the collision layer. Blocked
+    /// pixels are 0, walkable pixels are 255.
+    pub fn collision_mask(&self) -> Option<GrayImage> {
+        let layer = self.layers().iter().find(|layer| {
+            layer.layer_type == "IntGrid" && layer.identifier.to_lowercase() == COLLISION_LAYER
+        })?;
+        let mut mask = GrayImage::from_pixel(self.px_wid, self.px_hei, Luma([255]));
+        let grid_size = layer.grid_size as i32;
+        for (index, value) in layer.int_grid.iter().enumerate() {
+            if *value == 0 {
+                continue;
+            }
+            let left = (index as u32 % layer.columns) as i32 * grid_size + layer.offset_x;
+            let top = (index as u32 / layer.columns) as i32 * grid_size + layer.offset_y;
+            // Cells may exceed the level if the layer is moved
+            for y in top.max(0)..(top + grid_size).min(self.px_hei as i32) {
+                for x in left.max(0)..(left + grid_size).min(self.px_wid as i32) {
+                    mask.put_pixel(x as u32, y as u32, Luma([0]));
+                }
+            }
+        }
+        Some(mask)
+    }
+}
+
+#[test]
+fn test_ldtk_import() {
+    let project = br#"{
+        "jsonVersion": "1.5.3",
+        "externalLevels": false,
+        "levels": [
+            {"identifier": "Level_0", "pxWid": 64, "pxHei": 32, "bgRelPath": null,
+             "layerInstances": []},
+            {"identifier": "Level_1", "pxWid": 64, "pxHei": 32, "bgRelPath": "level_1.png",
+             "layerInstances": [
+                {"__identifier": "Entities", "__type": "Entities", "__cWid": 4, "__cHei": 2,
+                 "__gridSize": 16, "__pxTotalOffsetX": 0, "__pxTotalOffsetY": 0,
+                 "intGridCsv": [], "entityInstances": [
+                    {"__identifier": "Crystals", "__pivot": [0.5, 1], "iid": "a1",
+                     "width": 16, "height": 16, "px": [16, 32], "fieldInstances": [
+                        {"__identifier": "Amount", "__type": "Int", "__value": 3},
+                        {"__identifier": "Flip_x", "__type": "Bool", "__value": true},
+                        {"__identifier": "Animation", "__type": "String", "__value": null}
+                    ]}
+                 ]},
+                {"__identifier": "Collision", "__type": "IntGrid", "__cWid": 4, "__cHei": 2,
+                 "__gridSize": 16, "__pxTotalOffsetX": 0, "__pxTotalOffsetY": 0,
+                 "intGridCsv": [0, 1, 0, 0, 0, 0, 0, 2], "entityInstances": []}
+             ]}
+        ]
+    }"#;
+    let path = Path::new("world.ldtk");
+    let project = LdtkProject::from_bytes(path, project).unwrap();
+    assert_eq!(project.level(path, None).unwrap().identifier, "Level_0");
+    assert!(matches!(
+        project.level(path, Some("Level_2")),
+        Err(LoadError::UnknownLevel { .. })
+    ));
+
+    let level = project.level(path, Some("Level_1")).unwrap();
+    let map = level.to_map(path).unwrap();
+    assert_eq!(map.background, "level_1.png");
+    let bounds = map.bounds.unwrap();
+    assert_eq!((bounds.width, bounds.height), (64, 32));
+    let crystals = &map.entities["a1"];
+    assert_eq!(crystals.entity_type, "crystals");
+    assert_eq!((crystals.position.x, crystals.position.y), (-16, -8));
+    assert!(crystals.overrides.flip_x);
+    assert_eq!(crystals.overrides.animation, None);
+    assert_eq!(crystals.overrides.properties["amount"], Value::from(3));
+
+    let mask = level.collision_mask().unwrap();
+    assert_eq!(mask.dimensions(), (64, 32));
+    assert_eq!(mask.get_pixel(0, 0).0[0], 255);
+    assert_eq!(mask.get_pixel(16, 15).0[0], 0);
+    assert_eq!(mask.get_pixel(63, 31).0[0], 0);
+}
@@ -1,28 +1,518 @@
-use bevy::{ecs::system::Resource, utils::HashMap};
-use serde::Deserialize;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 
-use super::common::Position;
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::{Handle, Resource},
+    reflect::{TypePath, TypeUuid},
+    utils::{BoxedFuture, HashMap},
+};
+use image::GrayImage;
+use serde::{
+    de::{Error as _, MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_yaml::{Mapping, Value};
 
-#[derive(Resource)]
+use super::{
+    common::{Position, Size},
+    error::{LoadError, LoaderErrors},
+    ldtk::{is_ldtk_project, LdtkProject},
+    tiled::{is_tiled_map, TiledMap},
+};
+
+/// Path of the map file inside the asset folder which is used if no
+/// map is configured
+pub const MAP_FILE: &str = "map/entities.map.yaml";
+
+/// Split a map file into the path and the label selecting a level of
+/// an LDtk project, e.g. `map/world.ldtk#Level_1`
+pub fn split_label(map_file: &str) -> (&str, Option<&str>) {
+    match map_file.split_once('#') {
+        Some((path, label)) => (path, Some(label)),
+        None => (map_file, None),
+    }
+}
+
+/// Background image of maps in our own format relative to the map file
+pub const BACKGROUND_FILE: &str = "map.jpg";
+
+/// Layout of maps whose background and collision mask are streamed in
+/// chunks, relative to the map file. If it exists, it replaces the
+/// background image and the collision map.
+pub const CHUNKS_FILE: &str = "chunks.yaml";
+
+/// Path of the chunk layout of a map file without label
+pub fn chunks_path(map_file: &Path) -> PathBuf {
+    map_file.with_file_name(CHUNKS_FILE)
+}
+
+/// Prefix of trigger zones moving the player to another map, see
+/// [`door_target`]
+pub const DOOR_TRIGGER_PREFIX: &str = "door:";
+
+/// Map and spawn point a door leads to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DoorTarget<'a> {
+    /// Name of the map in the `MapRegistry`
+    pub map: &'a str,
+    /// Spawn point on the map, the player spawn is used if there is
+    /// none
+    pub spawn_point: Option<&'a str>,
+}
+
+/// Parse the name of a door trigger, which is either `door:<map>` or
+/// `door:<map>:<spawn point>`. `None` is returned for other triggers.
+pub fn door_target(trigger: &str) -> Option<DoorTarget<'_>> {
+    let target = trigger.strip_prefix(DOOR_TRIGGER_PREFIX)?;
+    let (map, spawn_point) = match target.split_once(':') {
+        Some((map, spawn_point)) => (map, Some(spawn_point)),
+        None => (target, None),
+    };
+    Some(DoorTarget { map, spawn_point })
+}
+
+/// Name of the map file entry containing the [`MapBounds`]
+const BOUNDS_ENTRY: &str = "bounds";
+
+/// Name of the layer defining the collision mask of imported maps
+pub const COLLISION_LAYER: &str = "collision";
+
+/// Properties of imported entities which are applied to the entity
+/// overrides instead of the instance properties
+const OVERRIDE_PROPERTIES: [&str; 6] = [
+    "flip_x",
+    "scale",
+    "z_offset",
+    "animation",
+    "collision",
+    "interaction",
+];
+
+/// Entities placed on the map. Maps are either written in our own
+/// YAML format, which only contains the entities, trigger zones, spawn
+/// points and optionally the bounds, or imported from Tiled or LDtk.
+#[derive(Resource, Deserialize, Debug, Clone, TypeUuid, TypePath)]
+#[uuid = "131bff96-dce8-4d3e-b319-eaef776e63d5"]
+#[serde(from = "MapEntries")]
 pub struct Map {
     pub entities: MapEntities,
+    pub triggers: MapTriggers,
+    /// Named positions where the player enters the map through doors
+    pub spawn_points: MapSpawnPoints,
+    /// Area the camera and the player stay in. The size of the
+    /// background image is used if there are no explicit bounds.
+    pub bounds: Option<MapBounds>,
+    /// Background image relative to the map file. Maps with explicit
+    /// bounds don't need one.
+    pub background: String,
+    /// Collision mask defined by an imported map
+    pub collision_mask: Option<GrayImage>,
+}
+
+impl From<MapEntries> for Map {
+    fn from(entries: MapEntries) -> Self {
+        let mut entities = MapEntities::default();
+        let mut triggers = MapTriggers::default();
+        let mut spawn_points = MapSpawnPoints::default();
+        for (name, entry) in entries.entries {
+            match entry {
+                MapEntry::Entity(entity) => {
+                    entities.insert(name, entity);
+                }
+                MapEntry::Trigger(trigger) => {
+                    triggers.insert(name, trigger);
+                }
+                MapEntry::SpawnPoint(spawn_point) => {
+                    spawn_points.insert(name, spawn_point);
+                }
+            }
+        }
+        Self {
+            entities,
+            triggers,
+            spawn_points,
+            bounds: entries.bounds,
+            background: BACKGROUND_FILE.to_owned(),
+            collision_mask: None,
+        }
+    }
+}
+
+impl Map {
+    /// Parse a map file. Tiled maps are detected by their extension.
+    pub fn from_bytes(path: &Path, bytes: &[u8]) -> Result<Self, LoadError> {
+        if is_tiled_map(path) {
+            TiledMap::from_bytes(path, bytes)?.to_map(path)
+        } else {
+            serde_yaml::from_slice(bytes).map_err(|e| LoadError::yaml(path, e))
+        }
+    }
+    /// Path of the background image inside the asset folder
+    pub fn background_path(&self, map_file: &Path) -> PathBuf {
+        map_file
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(&self.background)
+    }
+}
+
+#[derive(Resource)]
+pub struct MapHandle {
+    pub handle: Handle<Map>,
+    /// Map file of the current map inside the asset folder
+    pub map_file: String,
+}
+
+/// Handles of all maps of the `MapRegistry` by map name. All maps are
+/// loaded at startup, so they can be checked before the game starts.
+#[derive(Resource, Default)]
+pub struct RegistryMaps {
+    pub handles: HashMap<String, Handle<Map>>,
 }
 
 pub type MapEntities = HashMap<String, MapEntity>;
 
-#[derive(Deserialize, Debug)]
+pub type MapTriggers = HashMap<String, MapTrigger>;
+
+pub type MapSpawnPoints = HashMap<String, MapSpawnPoint>;
+
+/// Entry of a map file. Entries with a `trigger` are trigger zones,
+/// entries with `spawn_point: true` are spawn points and all other
+/// entries are entities.
+enum MapEntry {
+    Entity(MapEntity),
+    Trigger(MapTrigger),
+    SpawnPoint(MapSpawnPoint),
+}
+
+impl MapEntry {
+    fn from_value(value: Value) -> Result<Self, serde_yaml::Error> {
+        if value.get("trigger").is_some() {
+            serde_yaml::from_value(value).map(Self::Trigger)
+        } else if value.get("spawn_point") == Some(&Value::Bool(true)) {
+            serde_yaml::from_value(value).map(Self::SpawnPoint)
+        } else {
+            serde_yaml::from_value(value).map(Self::Entity)
+        }
+    }
+}
+
+/// Entries of a map file. Every entry is parsed on its own, so the
+/// errors mention the name of the entry. The name `bounds` is reserved
+/// for the bounds of the map.
+struct MapEntries {
+    entries: HashMap<String, MapEntry>,
+    bounds: Option<MapBounds>,
+}
+
+impl<'de> Deserialize<'de> for MapEntries {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor;
+
+        impl<'de> Visitor<'de> for EntriesVisitor {
+            type Value = MapEntries;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of entities and triggers")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<MapEntries, A::Error> {
+                let mut entries = HashMap::default();
+                let mut bounds = None;
+                while let Some(name) = map.next_key::<String>()? {
+                    let value = map.next_value()?;
+                    let error = |e: serde_yaml::Error| A::Error::custom(format!("{}: {}", name, e));
+                    if name == BOUNDS_ENTRY {
+                        bounds = Some(serde_yaml::from_value(value).map_err(error)?);
+                    } else {
+                        let entry = MapEntry::from_value(value).map_err(error)?;
+                        entries.insert(name, entry);
+                    }
+                }
+                Ok(MapEntries { entries, bounds })
+            }
+        }
+
+        deserializer.deserialize_map(EntriesVisitor)
+    }
+}
+
+/// Zone which sends trigger events when the player or other entities
+/// enter or leave it
+#[derive(Deserialize, Debug, Clone)]
+pub struct MapTrigger {
+    /// Name of the trigger sent with the events. Names starting with
+    /// `music:` select the music while the player is inside the zone,
+    /// e.g. `music:crystally`. Names starting with `door:` move the
+    /// player to another map, e.g. `door:spaceship:entrance`.
+    pub trigger: String,
+    /// Center of the zone
+    #[serde(flatten)]
+    pub position: Position,
+    #[serde(flatten)]
+    pub size: Size,
+}
+
+/// Rectangle given by its center and size. The center is the origin
+/// unless it is set.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapBounds {
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Position where the player enters the map
+#[derive(Deserialize, Debug, Clone)]
+pub struct MapSpawnPoint {
+    #[serde(flatten)]
+    pub position: Position,
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct MapEntity {
     #[serde(rename = "type")]
     pub entity_type: String,
     #[serde(flatten)]
     pub position: Position,
+    #[serde(flatten)]
+    pub overrides: EntityOverrides,
+}
+
+impl MapEntity {
+    /// Create an entity of an imported map. The entity is built as
+    /// YAML value so the same rules apply as for maps in our own
+    /// format.
+    pub fn from_properties(
+        entity_type: String,
+        x: f64,
+        y: f64,
+        properties: impl IntoIterator<Item = (String, Value)>,
+    ) -> Result<Self, serde_yaml::Error> {
+        let mut entity = Mapping::new();
+        entity.insert("type".into(), entity_type.into());
+        entity.insert("x".into(), (x.round() as i64).into());
+        entity.insert("y".into(), (y.round() as i64).into());
+        let mut instance_properties = Mapping::new();
+        for (name, value) in properties {
+            if OVERRIDE_PROPERTIES.contains(&name.as_str()) {
+                entity.insert(name.into(), value);
+            } else {
+                instance_properties.insert(name.into(), value);
+            }
+        }
+        entity.insert("properties".into(), Value::Mapping(instance_properties));
+        serde_yaml::from_value(Value::Mapping(entity))
+    }
+}
+
+/// Optional per instance changes to the entity type
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EntityOverrides {
+    /// Mirror the entity horizontally. The collision box and the
+    /// interaction position are mirrored, too.
+    pub flip_x: bool,
+    /// Scale of the entity including the collision box and the
+    /// interaction position
+    pub scale: f32,
+    /// Added to the z index calculated from the position
+    pub z_offset: f32,
+    /// Animation to start instead of the default animation
+    pub animation: Option<String>,
+    /// Disable collision if set to false
+    pub collision: bool,
+    pub interaction: InteractionOverrides,
+    /// Arbitrary values which can be used by systems handling
+    /// specific entities
+    pub properties: HashMap<String, serde_yaml::Value>,
+}
+
+impl Default for EntityOverrides {
+    fn default() -> Self {
+        Self {
+            flip_x: false,
+            scale: 1.0,
+            z_offset: 0.0,
+            animation: None,
+            collision: true,
+            interaction: InteractionOverrides::default(),
+            properties: HashMap::default(),
+        }
+    }
+}
+
+/// Replacements for the interaction of the entity type. They are
+/// ignored if the entity type has no interaction.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct InteractionOverrides {
+    pub name: Option<String>,
+    pub max_distance: Option<u16>,
+}
+
+#[derive(Default)]
+pub struct MapLoader {
+    pub errors: LoaderErrors,
+}
+
+impl AssetLoader for MapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(
+            async move { load_map(bytes, load_context).map_err(|error| self.errors.report(error)) },
+        )
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["map.yaml", "tmx", "tmj", "ldtk"]
+    }
+}
+
+fn load_map(bytes: &[u8], load_context: &mut LoadContext) -> Result<(), LoadError> {
+    let path = load_context.path().to_owned();
+    if is_ldtk_project(&path) {
+        // Every level is a labeled asset, the first level is also the
+        // default asset
+        let project = LdtkProject::from_bytes(&path, bytes)?;
+        for (index, level) in project.levels.iter().enumerate() {
+            let map = level.to_map(&path)?;
+            if index == 0 {
+                load_context.set_default_asset(LoadedAsset::new(map.clone()));
+            }
+            load_context.set_labeled_asset(&level.identifier, LoadedAsset::new(map));
+        }
+    } else {
+        let map = Map::from_bytes(&path, bytes)?;
+        load_context.set_default_asset(LoadedAsset::new(map));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_entity_overrides() {
+    let map: Map = serde_yaml::from_str(
+        "
+a:
+  type: crystals
+  x: 1
+  y: 2
+b:
+  type: crystals
+  x: 3
+  y: 4
+  flip_x: true
+  scale: 2
+  collision: false
+  interaction:
+    name: mine_gold
+  properties:
+    amount: 5
+",
+    )
+    .unwrap();
+    let a = &map.entities["a"].overrides;
+    assert!(!a.flip_x && a.collision);
+    assert_eq!(a.scale, 1.0);
+    let b = &map.entities["b"].overrides;
+    assert!(b.flip_x && !b.collision);
+    assert_eq!(b.scale, 2.0);
+    assert_eq!(b.interaction.name.as_deref(), Some("mine_gold"));
+    assert_eq!(b.interaction.max_distance, None);
+    assert_eq!(b.properties["amount"], serde_yaml::Value::from(5));
+}
+
+#[test]
+fn test_map_triggers() {
+    let map: Map = serde_yaml::from_str(
+        "
+a:
+  type: crystals
+  x: 1
+  y: 2
+cave:
+  trigger: music:crystally
+  x: 100
+  y: 0
+  width: 200
+  height: 50
+",
+    )
+    .unwrap();
+    assert_eq!(map.entities.len(), 1);
+    let cave = &map.triggers["cave"];
+    assert_eq!(cave.trigger, "music:crystally");
+    assert_eq!((cave.size.width, cave.size.height), (200, 50));
+
+    let error = serde_yaml::from_str::<Map>("a:\n  x: 1\n  y: 2\n").unwrap_err();
+    assert!(error.to_string().contains("a: missing field `type`"));
+}
+
+#[test]
+fn test_map_doors() {
+    let map: Map = serde_yaml::from_str(
+        "
+entrance:
+  spawn_point: true
+  x: 10
+  y: 20
+exit:
+  trigger: door:map:spaceship
+  x: 0
+  y: 0
+  width: 20
+  height: 20
+",
+    )
+    .unwrap();
+    assert!(map.entities.is_empty());
+    let entrance = &map.spawn_points["entrance"];
+    assert_eq!((entrance.position.x, entrance.position.y), (10, 20));
+    assert_eq!(
+        door_target(&map.triggers["exit"].trigger),
+        Some(DoorTarget {
+            map: "map",
+            spawn_point: Some("spaceship")
+        })
+    );
+    assert_eq!(
+        door_target("door:spaceship"),
+        Some(DoorTarget {
+            map: "spaceship",
+            spawn_point: None
+        })
+    );
+    assert_eq!(door_target("music:crystally"), None);
 }
 
-pub fn load_map() -> Result<Map, anyhow::Error> {
-    let file_name = "assets/map/entities.yaml";
-    let file = std::fs::File::open(file_name)
-        .unwrap_or_else(|e| panic!("Reading {:?} failed: {:?}", file_name, e));
-    let entities: MapEntities = serde_yaml::from_reader(file)
-        .unwrap_or_else(|e| panic!("Parsing {:?} failed: {:?}", file_name, e));
-    Ok(Map { entities })
+#[test]
+fn test_map_bounds() {
+    let map: Map =
+        serde_yaml::from_str("bounds:\n  y: 100\n  width: 800\n  height: 600\n").unwrap();
+    assert!(map.entities.is_empty());
+    assert_eq!(
+        map.bounds,
+        Some(MapBounds {
+            x: 0,
+            y: 100,
+            width: 800,
+            height: 600
+        })
+    );
+    assert_eq!(
+        serde_yaml::from_str::<Map>("a:\n  type: crystals_small\n  x: 0\n  y: 0\n")
+            .unwrap()
+            .bounds,
+        None
+    );
+    let error = serde_yaml::from_str::<Map>("bounds:\n  width: 800\n").unwrap_err();
+    assert!(error.to_string().contains("bounds: missing field `height`"));
 }
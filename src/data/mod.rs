@@ -1,3 +1,9 @@
+pub mod aseprite;
 pub mod common;
 pub mod entity_types;
+pub mod error;
+pub mod inheritance;
+pub mod ldtk;
 pub mod map;
+pub mod tiled;
+pub mod validate;
@@ -0,0 +1,620 @@
+//! Import of maps created with the Tiled map editor. Both the XML
+//! (`.tmx`) and the JSON (`.tmj`) format are supported. Only the parts
+//! of a Tiled map which have an equivalent in the game are read:
+//!
+//! - Objects of all object layers become map entities. The class of an
+//!   object is used as entity type and its custom properties become the
+//!   instance properties.
+//! - The first image layer is used as background image.
+//! - The tile layer named `collision` defines the collision mask. Every
+//!   tile placed on this layer blocks the area it covers.
+//!
+//! The origin of Tiled maps is the upper left corner while the game
+//! uses the center of the map. The background image is expected to
+//! cover the whole map.
+
+use std::{path::Path, str::FromStr};
+
+use bevy::utils::HashMap;
+use image::{GrayImage, Luma};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use serde::Deserialize;
+use serde_yaml::Value;
+
+use super::{
+    error::LoadError,
+    map::{
+        Map, MapBounds, MapEntities, MapEntity, MapSpawnPoints, MapTriggers, BACKGROUND_FILE,
+        COLLISION_LAYER,
+    },
+};
+
+/// Flag of a global tile id marking a horizontally flipped tile
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+
+/// Mask removing the flip flags from a global tile id
+const GID_MASK: u32 = 0x0fff_ffff;
+
+/// Check if the file is a Tiled map by its extension
+pub fn is_tiled_map(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|extension| extension.to_str()),
+        Some("tmx" | "tmj")
+    )
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TiledMap {
+    /// Width of the map in tiles
+    pub width: u32,
+    /// Height of the map in tiles
+    pub height: u32,
+    #[serde(rename = "tilewidth")]
+    pub tile_width: u32,
+    #[serde(rename = "tileheight")]
+    pub tile_height: u32,
+    #[serde(default)]
+    pub infinite: bool,
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum Layer {
+    #[serde(rename = "tilelayer")]
+    Tiles(TileLayer),
+    #[serde(rename = "objectgroup")]
+    Objects(ObjectLayer),
+    #[serde(rename = "imagelayer")]
+    Image(ImageLayer),
+    #[serde(rename = "group")]
+    Group(GroupLayer),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TileLayer {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// Missing for infinite maps which store their tiles in chunks
+    pub data: Option<TileData>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum TileData {
+    Gids(Vec<u32>),
+    /// Base64 encoded and optionally compressed tiles which are not
+    /// supported
+    Encoded(String),
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ObjectLayer {
+    pub name: String,
+    pub objects: Vec<Object>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ImageLayer {
+    pub name: String,
+    /// Path of the image relative to the map file. Tiled saves an
+    /// empty string if no image was selected.
+    pub image: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GroupLayer {
+    pub name: String,
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Object {
+    pub id: u32,
+    #[serde(default)]
+    pub name: String,
+    /// Class of the object which is used as entity type. Tiled 1.9
+    /// saved it as `class`, all other versions use `type`.
+    #[serde(rename = "type", alias = "class", default)]
+    pub class: String,
+    pub x: f64,
+    pub y: f64,
+    #[serde(default)]
+    pub width: f64,
+    #[serde(default)]
+    pub height: f64,
+    /// Tile of tile objects including the flip flags
+    pub gid: Option<u32>,
+    #[serde(default)]
+    pub properties: Vec<Property>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Property {
+    pub name: String,
+    pub value: Value,
+}
+
+impl TiledMap {
+    pub fn from_bytes(path: &Path, bytes: &[u8]) -> Result<Self, LoadError> {
+        let map: Self = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("tmx") => {
+                let text = std::str::from_utf8(bytes)
+                    .map_err(|e| LoadError::syntax(path, e.to_string()))?;
+                tmx_map(path, &Element::parse(path, text)?)?
+            }
+            _ => serde_json::from_slice(bytes).map_err(|e| LoadError::json(path, e))?,
+        };
+        if map.infinite {
+            return Err(LoadError::syntax(path, "infinite maps are not supported"));
+        }
+        Ok(map)
+    }
+
+    /// All layers including the layers nested in groups
+    pub fn layers(&self) -> Vec<&Layer> {
+        fn collect<'a>(layers: &'a [Layer], result: &mut Vec<&'a Layer>) {
+            for layer in layers {
+                result.push(layer);
+                if let Layer::Group(group) = layer {
+                    collect(&group.layers, result);
+                }
+            }
+        }
+        let mut result = Vec::new();
+        collect(&self.layers, &mut result);
+        result
+    }
+
+    pub fn to_map(&self, path: &Path) -> Result<Map, LoadError> {
+        let mut entities = MapEntities::default();
+        for layer in self.layers() {
+            let Layer::Objects(layer) = layer else {
+                continue;
+            };
+            for object in layer.objects.iter() {
+                let name = if object.name.is_empty() {
+                    format!("object_{}", object.id)
+                } else {
+                    object.name.clone()
+                };
+                if entities.contains_key(&name) {
+                    return Err(LoadError::DuplicateEntity {
+                        path: path.to_owned(),
+                        entity: name,
+                    });
+                }
+                entities.insert(name, self.map_entity(path, object)?);
+            }
+        }
+        let background = self
+            .layers()
+            .into_iter()
+            .find_map(|layer| match layer {
+                Layer::Image(layer) if !layer.image.is_empty() => Some(layer.image.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| BACKGROUND_FILE.to_owned());
+        Ok(Map {
+            entities,
+            triggers: MapTriggers::default(),
+            spawn_points: MapSpawnPoints::default(),
+            bounds: Some(MapBounds {
+                x: 0,
+                y: 0,
+                width: self.width * self.tile_width,
+                height: self.height * self.tile_height,
+            }),
+            background,
+            collision_mask: self.collision_mask(path)?,
+        })
+    }
+
+    /// Create the collision mask from the collision layer. Blocked
+    /// pixels are 0, walkable pixels are 255.
+    pub fn collision_mask(&self, path: &Path) -> Result<Option<GrayImage>, LoadError> {
+        let Some(layer) = self.layers().into_iter().find_map(|layer| match layer {
+            Layer::Tiles(layer) if layer.name == COLLISION_LAYER => Some(layer),
+            _ => None,
+        }) else {
+            return Ok(None);
+        };
+        let gids = layer.gids(path)?;
+        let (tile_width, tile_height) = (self.tile_width, self.tile_height);
+        let mut mask = GrayImage::from_pixel(
+            layer.width * tile_width,
+            layer.height * tile_height,
+            Luma([255]),
+        );
+        for (index, gid) in gids.iter().enumerate() {
+            if gid & GID_MASK == 0 {
+                continue;
+            }
+            let column = index as u32 % layer.width;
+            let row = index as u32 / layer.width;
+            for y in row * tile_height..(row + 1) * tile_height {
+                for x in column * tile_width..(column + 1) * tile_width {
+                    mask.put_pixel(x, y, Luma([0]));
+                }
+            }
+        }
+        Ok(Some(mask))
+    }
+
+    /// Convert an object to a map entity
+    fn map_entity(&self, path: &Path, object: &Object) -> Result<MapEntity, LoadError> {
+        let x = object.x + object.width / 2.0;
+        let mut y = object.y + object.height / 2.0;
+        if object.gid.is_some() {
+            // Tile objects are aligned to their lower left corner
+            y -= object.height;
+        }
+        let x = x - f64::from(self.width * self.tile_width) / 2.0;
+        let y = f64::from(self.height * self.tile_height) / 2.0 - y;
+
+        // Tile objects can be flipped in Tiled. A `flip_x` property
+        // still takes precedence.
+        let flip_x = object
+            .gid
+            .filter(|gid| gid & FLIPPED_HORIZONTALLY != 0)
+            .map(|_| ("flip_x".to_owned(), Value::from(true)));
+        let properties = flip_x.into_iter().chain(
+            object
+                .properties
+                .iter()
+                .map(|property| (property.name.clone(), property.value.clone())),
+        );
+        MapEntity::from_properties(object.class.clone(), x, y, properties)
+            .map_err(|e| LoadError::syntax(path, format!("object {}: {}", object.id, e)))
+    }
+}
+
+impl TileLayer {
+    fn gids(&self, path: &Path) -> Result<&[u32], LoadError> {
+        match &self.data {
+            Some(TileData::Gids(gids)) if gids.len() == (self.width * self.height) as usize => {
+                Ok(gids)
+            }
+            Some(TileData::Gids(gids)) => Err(LoadError::syntax(
+                path,
+                format!(
+                    "layer {:?}: expected {} tiles but found {}",
+                    self.name,
+                    self.width * self.height,
+                    gids.len()
+                ),
+            )),
+            _ => Err(LoadError::syntax(
+                path,
+                format!(
+                    "layer {:?}: only CSV encoded tile layers are supported",
+                    self.name
+                ),
+            )),
+        }
+    }
+}
+
+/// Minimal XML element tree used to read TMX files
+#[derive(Default)]
+struct Element {
+    name: String,
+    attributes: HashMap<String, String>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    /// Parse an XML document and return its root element
+    fn parse(path: &Path, text: &str) -> Result<Self, LoadError> {
+        let xml_error = |position: usize, message: String| LoadError::Syntax {
+            path: path.to_owned(),
+            line: Some(
+                text.as_bytes()[..position.min(text.len())]
+                    .iter()
+                    .filter(|byte| **byte == b'\n')
+                    .count()
+                    + 1,
+            ),
+            column: None,
+            message,
+        };
+        let mut reader = Reader::from_str(text);
+        reader.trim_text(true);
+        // The first element is the document containing the root
+        let mut stack = vec![Element::default()];
+        loop {
+            let event = reader
+                .read_event()
+                .map_err(|e| xml_error(reader.buffer_position(), e.to_string()))?;
+            match event {
+                Event::Start(start) => {
+                    let element = Self::new(&start)
+                        .map_err(|e| xml_error(reader.buffer_position(), e.to_string()))?;
+                    stack.push(element);
+                }
+                Event::Empty(start) => {
+                    let element = Self::new(&start)
+                        .map_err(|e| xml_error(reader.buffer_position(), e.to_string()))?;
+                    stack.last_mut().unwrap().children.push(element);
+                }
+                Event::End(_) => {
+                    let element = stack.pop().unwrap();
+                    let Some(parent) = stack.last_mut() else {
+                        return Err(xml_error(
+                            reader.buffer_position(),
+                            "unexpected end tag".into(),
+                        ));
+                    };
+                    parent.children.push(element);
+                }
+                Event::Text(content) => {
+                    let content = content
+                        .unescape()
+                        .map_err(|e| xml_error(reader.buffer_position(), e.to_string()))?;
+                    stack.last_mut().unwrap().text.push_str(&content);
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        let document = stack.pop().unwrap();
+        if !stack.is_empty() {
+            return Err(xml_error(text.len(), "unexpected end of file".into()));
+        }
+        document
+            .children
+            .into_iter()
+            .next()
+            .ok_or_else(|| xml_error(0, "missing root element".into()))
+    }
+
+    fn new(start: &BytesStart) -> Result<Self, quick_xml::Error> {
+        let mut attributes = HashMap::default();
+        for attribute in start.attributes() {
+            let attribute = attribute?;
+            attributes.insert(
+                String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+                attribute.unescape_value()?.into_owned(),
+            );
+        }
+        Ok(Self {
+            name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+            attributes,
+            ..Default::default()
+        })
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+
+    fn parse_attribute<T: FromStr>(&self, path: &Path, name: &str) -> Result<Option<T>, LoadError> {
+        self.attribute(name)
+            .map(|value| {
+                value.parse().map_err(|_| {
+                    LoadError::syntax(
+                        path,
+                        format!("{}: invalid value {:?} of {:?}", self.name, value, name),
+                    )
+                })
+            })
+            .transpose()
+    }
+
+    fn required_attribute<T: FromStr>(&self, path: &Path, name: &str) -> Result<T, LoadError> {
+        self.parse_attribute(path, name)?.ok_or_else(|| {
+            LoadError::syntax(path, format!("{}: missing attribute {:?}", self.name, name))
+        })
+    }
+}
+
+fn tmx_map(path: &Path, element: &Element) -> Result<TiledMap, LoadError> {
+    if element.name != "map" {
+        return Err(LoadError::syntax(path, "expected a map element"));
+    }
+    Ok(TiledMap {
+        width: element.required_attribute(path, "width")?,
+        height: element.required_attribute(path, "height")?,
+        tile_width: element.required_attribute(path, "tilewidth")?,
+        tile_height: element.required_attribute(path, "tileheight")?,
+        infinite: element.attribute("infinite") == Some("1"),
+        layers: tmx_layers(path, element)?,
+    })
+}
+
+fn tmx_layers(path: &Path, parent: &Element) -> Result<Vec<Layer>, LoadError> {
+    let mut layers = Vec::new();
+    for element in parent.children.iter() {
+        let name = element.attribute("name").unwrap_or_default().to_owned();
+        let layer = match element.name.as_str() {
+            "layer" => Layer::Tiles(TileLayer {
+                name,
+                width: element.required_attribute(path, "width")?,
+                height: element.required_attribute(path, "height")?,
+                data: element
+                    .child("data")
+                    .map(|data| tmx_tile_data(path, data))
+                    .transpose()?,
+            }),
+            "objectgroup" => Layer::Objects(ObjectLayer {
+                name,
+                objects: element
+                    .children
+                    .iter()
+                    .filter(|child| child.name == "object")
+                    .map(|object| tmx_object(path, object))
+                    .collect::<Result<_, _>>()?,
+            }),
+            "imagelayer" => Layer::Image(ImageLayer {
+                name,
+                image: element
+                    .child("image")
+                    .and_then(|image| image.attribute("source"))
+                    .unwrap_or_default()
+                    .to_owned(),
+            }),
+            "group" => Layer::Group(GroupLayer {
+                name,
+                layers: tmx_layers(path, element)?,
+            }),
+            _ => continue,
+        };
+        layers.push(layer);
+    }
+    Ok(layers)
+}
+
+fn tmx_tile_data(path: &Path, data: &Element) -> Result<TileData, LoadError> {
+    match data.attribute("encoding") {
+        Some("csv") => data
+            .text
+            .split(',')
+            .map(|gid| {
+                gid.trim()
+                    .parse()
+                    .map_err(|_| LoadError::syntax(path, format!("invalid tile {:?}", gid.trim())))
+            })
+            .collect::<Result<_, _>>()
+            .map(TileData::Gids),
+        // Without encoding every tile is stored as element
+        None => data
+            .children
+            .iter()
+            .filter(|child| child.name == "tile")
+            .map(|tile| Ok(tile.parse_attribute(path, "gid")?.unwrap_or(0)))
+            .collect::<Result<_, _>>()
+            .map(TileData::Gids),
+        Some(_) => Ok(TileData::Encoded(data.text.clone())),
+    }
+}
+
+fn tmx_object(path: &Path, object: &Element) -> Result<Object, LoadError> {
+    Ok(Object {
+        id: object.required_attribute(path, "id")?,
+        name: object.attribute("name").unwrap_or_default().to_owned(),
+        class: object
+            .attribute("type")
+            .or_else(|| object.attribute("class"))
+            .unwrap_or_default()
+            .to_owned(),
+        x: object.required_attribute(path, "x")?,
+        y: object.required_attribute(path, "y")?,
+        width: object.parse_attribute(path, "width")?.unwrap_or_default(),
+        height: object.parse_attribute(path, "height")?.unwrap_or_default(),
+        gid: object.parse_attribute(path, "gid")?,
+        properties: tmx_properties(path, object)?,
+    })
+}
+
+/// Read the custom properties of an element. The values are converted
+/// to the types used by the JSON format.
+fn tmx_properties(path: &Path, element: &Element) -> Result<Vec<Property>, LoadError> {
+    let Some(properties) = element.child("properties") else {
+        return Ok(Vec::new());
+    };
+    let mut result = Vec::new();
+    for property in properties
+        .children
+        .iter()
+        .filter(|child| child.name == "property")
+    {
+        let name: String = property.required_attribute(path, "name")?;
+        let value = match property.attribute("type").unwrap_or("string") {
+            "bool" => Value::from(property.required_attribute::<bool>(path, "value")?),
+            "int" | "object" => Value::from(property.required_attribute::<i64>(path, "value")?),
+            "float" => Value::from(property.required_attribute::<f64>(path, "value")?),
+            "class" => Value::Mapping(
+                tmx_properties(path, property)?
+                    .into_iter()
+                    .map(|member| (Value::from(member.name), member.value))
+                    .collect(),
+            ),
+            // Multiline strings are stored as text instead of attribute
+            _ => Value::from(
+                property
+                    .attribute("value")
+                    .unwrap_or(property.text.as_str())
+                    .to_owned(),
+            ),
+        };
+        result.push(Property { name, value });
+    }
+    Ok(result)
+}
+
+#[test]
+fn test_tiled_import() {
+    let tmj = br#"{
+        "width": 4, "height": 2, "tilewidth": 16, "tileheight": 16,
+        "layers": [
+            {"type": "imagelayer", "name": "background", "image": "level.png"},
+            {"type": "tilelayer", "name": "collision", "width": 4, "height": 2,
+             "data": [0, 1, 0, 0, 0, 0, 0, 2147483650]},
+            {"type": "group", "name": "entities", "layers": [
+                {"type": "objectgroup", "name": "objects", "objects": [
+                    {"id": 1, "name": "c1", "type": "crystals", "x": 8, "y": 8,
+                     "width": 16, "height": 16, "properties": [
+                        {"name": "amount", "type": "int", "value": 3},
+                        {"name": "scale", "type": "float", "value": 0.5}
+                    ]},
+                    {"id": 2, "type": "engine", "x": 32, "y": 32,
+                     "width": 16, "height": 16, "gid": 2147483649}
+                ]}
+            ]}
+        ]
+    }"#;
+    let tmx = br#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" width="4" height="2" tilewidth="16" tileheight="16" infinite="0">
+ <imagelayer id="1" name="background">
+  <image source="level.png" width="64" height="32"/>
+ </imagelayer>
+ <layer id="2" name="collision" width="4" height="2">
+  <data encoding="csv">
+0,1,0,0,
+0,0,0,2147483650
+</data>
+ </layer>
+ <group id="3" name="entities">
+  <objectgroup id="4" name="objects">
+   <object id="1" name="c1" type="crystals" x="8" y="8" width="16" height="16">
+    <properties>
+     <property name="amount" type="int" value="3"/>
+     <property name="scale" type="float" value="0.5"/>
+    </properties>
+   </object>
+   <object id="2" type="engine" gid="2147483649" x="32" y="32" width="16" height="16"/>
+  </objectgroup>
+ </group>
+</map>"#;
+    for (file, bytes) in [("level.tmj", &tmj[..]), ("level.tmx", &tmx[..])] {
+        let path = Path::new(file);
+        let tiled = TiledMap::from_bytes(path, bytes).unwrap();
+        let map = tiled.to_map(path).unwrap();
+        assert_eq!(map.background, "level.png");
+        let bounds = map.bounds.unwrap();
+        assert_eq!((bounds.width, bounds.height), (64, 32));
+        let c1 = &map.entities["c1"];
+        assert_eq!(c1.entity_type, "crystals");
+        assert_eq!((c1.position.x, c1.position.y), (-16, 0));
+        assert_eq!(c1.overrides.scale, 0.5);
+        assert_eq!(c1.overrides.properties["amount"], Value::from(3));
+        let engine = &map.entities["object_2"];
+        assert_eq!((engine.position.x, engine.position.y), (8, -8));
+        assert!(engine.overrides.flip_x);
+
+        let mask = tiled.collision_mask(path).unwrap().unwrap();
+        assert_eq!(mask.dimensions(), (64, 32));
+        assert_eq!(mask.get_pixel(0, 0).0[0], 255);
+        assert_eq!(mask.get_pixel(16, 15).0[0], 0);
+        assert_eq!(mask.get_pixel(63, 31).0[0], 0);
+    }
+}
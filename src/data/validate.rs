@@ -0,0 +1,330 @@
+use std::path::{Path, PathBuf};
+
+use bevy::{math::Vec3, utils::HashMap};
+
+use crate::{
+    components::{
+        animation::DEFAULT_ANIMATION,
+        player::{PLAYER_ANIMATIONS, PLAYER_ENTITY_TYPE, PLAYER_SPAWN},
+    },
+    resources::{
+        chunks::{background_chunk_file, collision_chunk_file, ChunkLayout},
+        map::Map as CollisionMap,
+        map_registry::MapRegistry,
+    },
+};
+
+use super::{
+    entity_types::{EntityImage, EntityType, ENTITY_TYPES_DIR, ENTITY_TYPE_EXTENSION},
+    error::{LoadError, LoadErrors},
+    map::{chunks_path, door_target, Map, MapEntity},
+};
+
+/// Game data loaded by the `AssetServer` together with the paths of
+/// the files inside the asset folder, which are used in the errors
+pub struct GameData {
+    pub entity_types: HashMap<String, (PathBuf, EntityType)>,
+    /// Maps of the `MapRegistry` by map name. Maps which failed to
+    /// load are missing.
+    pub maps: HashMap<String, (PathBuf, Map)>,
+}
+
+/// Check that all references between the entity types and the maps
+/// can be resolved. Errors are added to `errors`, so all of them can be
+/// reported at once.
+pub fn check_game_data(
+    asset_dir: &Path,
+    data: &GameData,
+    registry: &MapRegistry,
+    errors: &mut LoadErrors,
+) {
+    for (name, (path, entity_type)) in data.entity_types.iter() {
+        check_entity_type(asset_dir, name, path, entity_type, errors);
+    }
+    if !data.entity_types.contains_key(PLAYER_ENTITY_TYPE) {
+        errors.push(LoadError::MissingFile {
+            path: asset_dir
+                .join(ENTITY_TYPES_DIR)
+                .join(format!("{}.{}", PLAYER_ENTITY_TYPE, ENTITY_TYPE_EXTENSION)),
+        });
+    }
+    check_maps(data, registry, errors);
+}
+
+/// Check that the images of an entity type exist, the collision shapes,
+/// the interaction and the sprite sheet animations fit inside of the
+/// entity and the player has all animations it needs
+pub fn check_entity_type(
+    asset_dir: &Path,
+    name: &str,
+    path: &Path,
+    entity_type: &EntityType,
+    errors: &mut LoadErrors,
+) {
+    let path = path.to_owned();
+    for image in entity_type.image.image_paths() {
+        if !asset_dir.join(&image).is_file() {
+            errors.push(LoadError::MissingImage {
+                path: path.clone(),
+                image,
+            });
+        }
+    }
+    let size = entity_type.size;
+    if let Some(collision) = &entity_type.collision {
+        for shape in collision.0.iter() {
+            let ((left, top), (right, bottom)) = shape.bounds();
+            if left < 0
+                || top < 0
+                || right > i32::from(size.width)
+                || bottom > i32::from(size.height)
+            {
+                errors.push(LoadError::CollisionOutOfBounds { path: path.clone() });
+            }
+            if !shape.is_valid() {
+                errors.push(LoadError::InvalidCollisionPolygon { path: path.clone() });
+            }
+        }
+    }
+    if let Some(interaction) = &entity_type.interaction {
+        let position = interaction.position;
+        if position.x < 0
+            || position.y < 0
+            || i32::from(position.x) > i32::from(size.width)
+            || i32::from(position.y) > i32::from(size.height)
+        {
+            errors.push(LoadError::InteractionOutOfBounds { path: path.clone() });
+        }
+    }
+    if let EntityImage::SpriteSheet(sprite_sheet) = &entity_type.image {
+        let frame_count = sprite_sheet.columns * sprite_sheet.rows;
+        for (animation, range) in sprite_sheet.animations.iter() {
+            if range.first > range.last || range.last >= frame_count {
+                errors.push(LoadError::InvalidFrameRange {
+                    path: path.clone(),
+                    animation: animation.clone(),
+                    first: range.first,
+                    last: range.last,
+                    frame_count,
+                });
+            }
+        }
+    }
+    if name == PLAYER_ENTITY_TYPE {
+        let animation_names = entity_type.image.animation_names();
+        for animation in PLAYER_ANIMATIONS {
+            if !animation_names.contains(&animation) {
+                errors.push(LoadError::UnknownAnimation {
+                    path: path.clone(),
+                    animation: animation.to_owned(),
+                });
+            }
+        }
+    }
+}
+
+/// Check that the background of a map or its chunks exist and all
+/// entities reference existing entity types and animations. The
+/// background is optional if the map has explicit bounds.
+fn check_map(path: &Path, map: &Map, data: &GameData, errors: &mut LoadErrors) {
+    let path = path.to_owned();
+    let chunks = chunks_path(&path);
+    if chunks.is_file() {
+        check_chunks(&chunks, errors);
+    } else {
+        let background = map.background_path(&path);
+        if map.bounds.is_none() && !background.is_file() {
+            errors.push(LoadError::MissingImage {
+                path: path.clone(),
+                image: background,
+            });
+        }
+    }
+    for (name, entity) in map.entities.iter() {
+        let Some((_, entity_type)) = data.entity_types.get(&entity.entity_type) else {
+            errors.push(LoadError::UnknownEntityType {
+                path: path.clone(),
+                entity: name.clone(),
+                entity_type: entity.entity_type.clone(),
+            });
+            continue;
+        };
+        check_map_entity(&path, entity, entity_type, errors);
+    }
+}
+
+/// Check that the animation a map entity starts with exists
+pub fn check_map_entity(
+    path: &Path,
+    entity: &MapEntity,
+    entity_type: &EntityType,
+    errors: &mut LoadErrors,
+) {
+    let animation = match &entity.overrides.animation {
+        Some(animation) => animation.as_str(),
+        None if matches!(entity_type.image, EntityImage::Static(_)) => return,
+        // The initial animation only fails if there are no animations
+        None => entity_type
+            .image
+            .initial_animation()
+            .unwrap_or(DEFAULT_ANIMATION),
+    };
+    if !entity_type.image.animation_names().contains(&animation) {
+        errors.push(LoadError::UnknownAnimation {
+            path: path.to_owned(),
+            animation: animation.to_owned(),
+        });
+    }
+}
+
+/// Check that the background and collision images of all chunks exist.
+/// The images are only decoded while streaming.
+fn check_chunks(path: &Path, errors: &mut LoadErrors) {
+    let Some(layout) = errors.collect(ChunkLayout::load(path)) else {
+        return;
+    };
+    let dir = path.parent().unwrap_or(Path::new(""));
+    for chunk in layout.chunks() {
+        let background = background_chunk_file(dir, chunk);
+        if !background.is_file() {
+            errors.push(LoadError::MissingImage {
+                path: path.to_owned(),
+                image: background,
+            });
+        }
+        let collision = collision_chunk_file(dir, chunk);
+        if !collision.is_file() {
+            errors.push(LoadError::MissingFile { path: collision });
+        }
+    }
+}
+
+/// Check all maps of the registry and that all doors lead to existing
+/// maps and spawn points
+fn check_maps(data: &GameData, registry: &MapRegistry, errors: &mut LoadErrors) {
+    let maps = &data.maps;
+    for (path, map) in maps.values() {
+        check_map(path, map, data, errors);
+    }
+    for (path, map) in maps.values() {
+        for trigger in map.triggers.values() {
+            let Some(target) = door_target(&trigger.trigger) else {
+                continue;
+            };
+            if registry.get(target.map).is_none() {
+                errors.push(LoadError::UnknownMap {
+                    path: path.clone(),
+                    map: target.map.to_owned(),
+                });
+                continue;
+            }
+            let (Some(spawn_point), Some((_, target_map))) =
+                (target.spawn_point, maps.get(target.map))
+            else {
+                continue;
+            };
+            if !target_map.spawn_points.contains_key(spawn_point) {
+                errors.push(LoadError::UnknownSpawnPoint {
+                    path: path.clone(),
+                    map: target.map.to_owned(),
+                    spawn_point: spawn_point.to_owned(),
+                });
+            }
+        }
+    }
+}
+
+/// Check that no map entity or spawn point is placed on blocked terrain
+/// of the collision map. The player spawn is checked as well if the
+/// player can appear there, `player` is the path of its entity type.
+pub fn check_spawn_points(
+    path: &Path,
+    map: &Map,
+    player: Option<&Path>,
+    collision_map: &CollisionMap,
+    errors: &mut LoadErrors,
+) {
+    if let Some(player) = player {
+        if collision_map.is_blocked(PLAYER_SPAWN) {
+            errors.push(LoadError::BlockedSpawnPoint {
+                path: player.to_owned(),
+                entity: PLAYER_ENTITY_TYPE.to_owned(),
+                x: PLAYER_SPAWN.x,
+                y: PLAYER_SPAWN.y,
+            });
+        }
+    }
+    for (name, entity) in map.entities.iter() {
+        let position = Vec3::new(entity.position.x.into(), entity.position.y.into(), 0.0);
+        if collision_map.is_blocked(position) {
+            errors.push(LoadError::BlockedSpawnPoint {
+                path: path.to_owned(),
+                entity: name.clone(),
+                x: position.x,
+                y: position.y,
+            });
+        }
+    }
+    for (name, spawn_point) in map.spawn_points.iter() {
+        let position = spawn_point.position;
+        let position = Vec3::new(position.x.into(), position.y.into(), 0.0);
+        if collision_map.is_blocked(position) {
+            errors.push(LoadError::BlockedSpawnPoint {
+                path: path.to_owned(),
+                entity: name.clone(),
+                x: position.x,
+                y: position.y,
+            });
+        }
+    }
+}
+
+pub fn read_yaml<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, LoadError> {
+    let bytes = std::fs::read(path).map_err(|e| LoadError::io(path, e))?;
+    serde_yaml::from_slice(&bytes).map_err(|e| LoadError::yaml(path, e))
+}
+
+pub fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, LoadError> {
+    let bytes = std::fs::read(path).map_err(|e| LoadError::io(path, e))?;
+    serde_json::from_slice(&bytes).map_err(|e| LoadError::json(path, e))
+}
+
+#[test]
+fn test_sprite_sheet_ranges() {
+    let entity_type: EntityType = serde_yaml::from_str(
+        "
+width: 8
+height: 8
+sprite_sheet:
+  image: sheet.png
+  tile:
+    width: 8
+    height: 8
+  columns: 2
+  rows: 2
+  animations:
+    idle: { first: 0, last: 3, duration: 100 }
+    walk: { first: 2, last: 4, duration: 100 }
+    jump: { first: 3, last: 1, duration: 100 }
+",
+    )
+    .unwrap();
+    let mut errors = LoadErrors::default();
+    check_entity_type(
+        Path::new(""),
+        "sheet",
+        Path::new("sheet.entity.yaml"),
+        &entity_type,
+        &mut errors,
+    );
+    let mut invalid = errors
+        .errors
+        .iter()
+        .filter_map(|error| match error {
+            LoadError::InvalidFrameRange { animation, .. } => Some(animation.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    invalid.sort();
+    assert_eq!(invalid, ["jump", "walk"]);
+}
@@ -0,0 +1,474 @@
+use std::{
+    iter,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use bevy::{
+    asset::FileAssetIo,
+    ecs::system::EntityCommands,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use image::GrayImage;
+
+use components::{
+    animation::{Animation, AnimationState, DEFAULT_ANIMATION},
+    collision::Collision,
+    entity_type::EntityTypeName,
+    interaction::Interaction,
+    player::PLAYER_SPAWN,
+};
+use data::{
+    aseprite::{AsepriteSheet, AsepriteSheetLoader},
+    entity_types::{CollisionLayer, EntityType, EntityTypeLoader, EntityTypes, Loaded},
+    error::{LoadError, LoadErrors, LoaderErrors},
+    inheritance::{EntityTypeTemplate, EntityTypeTemplateLoader},
+    map::{chunks_path, split_label, EntityOverrides, Map as MapData, MapLoader, RegistryMaps},
+};
+use resources::{
+    config::Config,
+    map::Map,
+    map_registry::{MapFiles, MapRegistry},
+    navmesh::nav_mesh_path,
+    pathfinding::NavGrid,
+};
+use systems::{
+    animation::AnimationTimer,
+    data::{check_data, load_data},
+};
+
+pub mod components;
+pub mod data;
+pub mod helpers;
+pub mod resources;
+pub mod systems;
+
+/// Folder containing the game assets
+pub const ASSET_DIR: &str = "assets";
+
+#[derive(States, Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub enum AppState {
+    #[default]
+    Loading,
+    Setup,
+    Finished,
+}
+
+#[derive(Resource, Default)]
+pub struct ImageHandles {
+    handles: Vec<Handle<Image>>,
+}
+
+impl ImageHandles {
+    pub fn add(&mut self, handle: Handle<Image>) -> usize {
+        let index = self.handles.len();
+        self.handles.push(handle);
+        index
+    }
+}
+
+pub fn spawn_entity(
+    commands: &mut Commands,
+    entity_type_name: &str,
+    entity_type: &EntityType,
+    translation: Vec3,
+    animation_name: Option<&str>,
+    overrides: &EntityOverrides,
+    f: impl FnOnce(&mut EntityCommands),
+) {
+    let mut entity_cmds = commands.spawn(EntityTypeName {
+        name: entity_type_name.to_owned(),
+    });
+    insert_entity_type(
+        &mut entity_cmds,
+        entity_type,
+        translation,
+        animation_name,
+        overrides,
+    );
+    f(&mut entity_cmds);
+}
+
+/// Insert the components derived from an entity type. This is also
+/// used to update existing entities when their entity type was
+/// reloaded, so components of the previous entity type are removed
+/// first. The `animation_name` takes precedence over the animation of
+/// the `overrides`. The `z_offset` of the `overrides` is added here, so
+/// the `translation` must not contain it, see [`base_translation`].
+pub fn insert_entity_type(
+    entity_cmds: &mut EntityCommands,
+    entity_type: &EntityType,
+    mut translation: Vec3,
+    animation_name: Option<&str>,
+    overrides: &EntityOverrides,
+) {
+    entity_cmds.remove::<(
+        Collision,
+        Interaction,
+        Animation,
+        AnimationState,
+        AnimationTimer,
+    )>();
+    let collision = entity_type_collision(entity_type, overrides).map(|mut collision| {
+        translation.z = collision.update_position(translation);
+        collision
+    });
+    translation.z += overrides.z_offset;
+    let transform = Transform {
+        translation,
+        scale: Vec3::new(overrides.scale, overrides.scale, 1.0),
+        ..Default::default()
+    };
+    match entity_type.loaded.as_ref().unwrap() {
+        Loaded::Static(handle) => {
+            entity_cmds.remove::<SpriteSheetBundle>();
+            entity_cmds.insert(SpriteBundle {
+                texture: handle.clone(),
+                sprite: Sprite {
+                    flip_x: overrides.flip_x,
+                    ..Default::default()
+                },
+                transform,
+                ..Default::default()
+            });
+        }
+        Loaded::Animation(animation) => {
+            let frames = [(DEFAULT_ANIMATION.to_owned(), animation.frames.clone())]
+                .into_iter()
+                .collect();
+            insert_animation(
+                entity_cmds,
+                animation.atlas.clone(),
+                frames,
+                Some(DEFAULT_ANIMATION),
+                transform,
+                overrides.flip_x,
+            );
+        }
+        Loaded::Animations(animations) => {
+            // Unknown animations are reported by the checks, entities
+            // start the initial animation instead
+            let animation_name = [animation_name, overrides.animation.as_deref()]
+                .into_iter()
+                .flatten()
+                .find(|name| animations.frames.contains_key(*name))
+                .or_else(|| entity_type.image.initial_animation());
+            insert_animation(
+                entity_cmds,
+                animations.atlas.clone(),
+                animations.frames.clone(),
+                animation_name,
+                transform,
+                overrides.flip_x,
+            );
+        }
+    }
+    if let Some(collision) = collision.filter(|_| overrides.collision) {
+        entity_cmds.insert(collision);
+    }
+    if let Some(interaction) = &entity_type.interaction {
+        let mut offset = Vec3::new(
+            f32::from(interaction.position.x) - f32::from(entity_type.size.width) / 2.0,
+            f32::from(entity_type.size.height) / 2.0 - f32::from(interaction.position.y),
+            0.0,
+        );
+        if overrides.flip_x {
+            offset.x = -offset.x;
+        }
+        entity_cmds.insert(Interaction {
+            name: overrides
+                .interaction
+                .name
+                .clone()
+                .unwrap_or_else(|| interaction.name.clone()),
+            center: Vec3::new(translation.x, translation.y, 0.0) + offset * overrides.scale,
+            max_distance: overrides
+                .interaction
+                .max_distance
+                .unwrap_or(interaction.max_distance),
+        });
+    }
+}
+
+/// Translation of an existing entity without the `z_offset` of its
+/// overrides, as [`insert_entity_type`] expects it
+pub fn base_translation(transform: &Transform, overrides: &EntityOverrides) -> Vec3 {
+    transform.translation - Vec3::new(0.0, 0.0, overrides.z_offset)
+}
+
+/// Collision of an entity of this type, scaled and flipped by the
+/// `overrides`. The position still has to be updated.
+pub fn entity_type_collision(
+    entity_type: &EntityType,
+    overrides: &EntityOverrides,
+) -> Option<Collision> {
+    entity_type.collision.as_ref().map(|collision| {
+        let mut collision = Collision::from_data(entity_type.size, collision);
+        collision.layer = entity_type.collision_layer.bit();
+        if let Some(mask) = &entity_type.collision_mask {
+            collision.mask = CollisionLayer::mask(mask);
+        }
+        collision.transform(overrides.scale, overrides.flip_x);
+        collision
+    })
+}
+
+/// Insert the sprite and the animation components. Without any
+/// animation the first frame of the atlas is shown.
+pub fn insert_animation(
+    entity_cmds: &mut EntityCommands,
+    atlas: Handle<TextureAtlas>,
+    frames: HashMap<String, Vec<(usize, Duration)>>,
+    animation_name: Option<&str>,
+    transform: Transform,
+    flip_x: bool,
+) {
+    let animation_name = animation_name.filter(|name| {
+        frames
+            .get(*name)
+            .is_some_and(|animation_frames| !animation_frames.is_empty())
+    });
+    let first_frame = animation_name.map_or(0, |name| frames[name][0].0);
+    entity_cmds.remove::<SpriteBundle>();
+    entity_cmds.insert(SpriteSheetBundle {
+        texture_atlas: atlas,
+        sprite: TextureAtlasSprite {
+            index: first_frame,
+            flip_x,
+            ..Default::default()
+        },
+        transform,
+        ..Default::default()
+    });
+    let Some(animation_name) = animation_name else {
+        return;
+    };
+    entity_cmds.insert(Animation { frames });
+    entity_cmds.insert(AnimationState {
+        animation: animation_name.to_owned(),
+        restart: true,
+        index: 0,
+    });
+    // The duration is replaced by the one of the first frame as
+    // `restart` is set.
+    entity_cmds.insert(AnimationTimer::from_seconds(0.1));
+}
+
+/// Folder the `AssetServer` loads the assets from. Files which are
+/// read without the `AssetServer`, like collision maps, are read from
+/// here as well, so a configured asset root applies to them too.
+pub fn asset_dir() -> PathBuf {
+    FileAssetIo::get_base_path().join(ASSET_DIR)
+}
+
+/// Load the data needed before the app is created. The remaining game
+/// data is loaded by the `AssetServer` and checked by `check_data`.
+pub fn load() -> Result<(Config, MapRegistry), LoadErrors> {
+    let mut errors = LoadErrors::default();
+    let config = errors.collect(Config::load());
+    let registry = config
+        .as_ref()
+        .map(|config| MapRegistry::scan(&asset_dir(), config, &mut errors));
+    errors.into_result(())?;
+    Ok((config.unwrap(), registry.unwrap()))
+}
+
+/// Register the game data assets and the systems loading and checking
+/// them. The game and `sauerstoff-check` share this, so both check the
+/// data the game loads. The `AssetPlugin` has to be added before.
+pub fn add_game_data(app: &mut App) {
+    let loader_errors = LoaderErrors::default();
+    app.add_state::<AppState>();
+    app.init_resource::<EntityTypes>();
+    app.insert_resource(loader_errors.clone());
+    app.add_asset::<EntityType>();
+    app.add_asset_loader(EntityTypeLoader {
+        errors: loader_errors.clone(),
+    });
+    app.add_asset::<EntityTypeTemplate>();
+    app.add_asset_loader(EntityTypeTemplateLoader {
+        errors: loader_errors.clone(),
+    });
+    app.add_asset::<AsepriteSheet>();
+    app.add_asset_loader(AsepriteSheetLoader);
+    app.add_asset::<MapData>();
+    app.add_asset_loader(MapLoader {
+        errors: loader_errors,
+    });
+    app.add_systems(Startup, load_data);
+    app.add_systems(Update, check_data.run_if(in_state(AppState::Loading)));
+}
+
+/// Load and check the game data without opening a window, like the
+/// game does. The tools share this. Errors are printed before exiting,
+/// otherwise `f` is called with the world holding the checked data.
+pub fn run_headless(f: impl FnOnce(&mut World) + Send + 'static) {
+    let (config, map_registry) = load().unwrap_or_else(|errors| {
+        eprint!("{}", errors);
+        std::process::exit(1);
+    });
+
+    let mut app = App::new();
+    app.insert_resource(config);
+    app.insert_resource(map_registry);
+    app.add_plugins((MinimalPlugins, AssetPlugin::default()));
+    add_game_data(&mut app);
+    app.set_runner(move |mut app| loop {
+        app.update();
+        if let Some(errors) = app.world.get_resource::<LoadErrors>() {
+            eprint!("{}", errors);
+            std::process::exit(1);
+        }
+        if *app.world.resource::<State<AppState>>() != AppState::Loading {
+            f(&mut app.world);
+            return;
+        }
+        // Wait for the asset server to load the data
+        thread::sleep(Duration::from_millis(10));
+    });
+    app.run();
+}
+
+/// Navigation grids of the maps of the registry which are not streamed
+/// in chunks by the path of their baked navigation mesh. The grids are
+/// built from the collision map and the colliders of the map entities
+/// like in the game when the map is entered.
+pub fn static_nav_grids(world: &World, errors: &mut LoadErrors) -> Vec<(PathBuf, NavGrid)> {
+    let asset_dir = asset_dir();
+    let config = world.resource::<Config>();
+    let registry = world.resource::<MapRegistry>();
+    let entity_types = world.resource::<EntityTypes>();
+    let maps = world.resource::<Assets<MapData>>();
+    let mut grids = Vec::new();
+    for (name, handle) in world.resource::<RegistryMaps>().handles.iter() {
+        // The maps were checked, so all of them are loaded
+        let (Some(files), Some(map)) = (registry.maps.get(name), maps.get(handle)) else {
+            continue;
+        };
+        let Some(collision_map) =
+            errors.collect(load_collision_map(&asset_dir, files, map, config))
+        else {
+            continue;
+        };
+        if let Map::Chunked(_) = collision_map {
+            continue;
+        }
+        let colliders = map
+            .entities
+            .values()
+            .filter(|entity| entity.overrides.collision)
+            .filter_map(|entity| {
+                let entity_type = entity_types.map.get(&entity.entity_type)?;
+                let mut collision = entity_type_collision(entity_type, &entity.overrides)?;
+                collision.update_position(Vec3::new(
+                    entity.position.x.into(),
+                    entity.position.y.into(),
+                    1.0,
+                ));
+                Some(collision)
+            })
+            .filter(|collision| !collision.sensor)
+            .collect::<Vec<_>>();
+        let colliders = colliders.iter().collect::<Vec<_>>();
+        grids.push((
+            asset_dir.join(nav_mesh_path(&files.map)),
+            NavGrid::build(&collision_map, &colliders),
+        ));
+    }
+    grids
+}
+
+/// Load the collision map of a map from the registry as configured
+pub fn load_collision_map(
+    asset_dir: &Path,
+    files: &MapFiles,
+    map: &MapData,
+    config: &Config,
+) -> Result<Map, LoadError> {
+    let mut map = collision_map_from(asset_dir, files, map.collision_mask.clone())?;
+    if !config.collision_map_terrain {
+        map = map.without_terrain();
+    }
+    Ok(if config.trace_collision_map {
+        map.traced()
+    } else {
+        map
+    })
+}
+
+/// Load the chunks of a streamed collision map below the entities and
+/// spawn points of the map and below the player spawn. This way the
+/// player starts on a loaded chunk and the spawn points can be checked.
+pub fn load_spawn_chunks(collision_map: &mut Map, map: &MapData) -> Result<(), LoadError> {
+    let Map::Chunked(chunks) = collision_map else {
+        return Ok(());
+    };
+    let entities = map.entities.values().map(|entity| entity.position);
+    let spawn_points = map.spawn_points.values().map(|point| point.position);
+    let chunks_to_load = entities
+        .chain(spawn_points)
+        .map(|position| Vec3::new(position.x.into(), position.y.into(), 0.0))
+        .chain(iter::once(PLAYER_SPAWN))
+        .filter_map(|position| chunks.layout.chunk_at(position))
+        .collect::<HashSet<_>>();
+    for chunk in chunks_to_load {
+        chunks.load_chunk(chunk)?;
+    }
+    Ok(())
+}
+
+/// Collision of a map. Maps streamed in chunks take precedence over the
+/// collision mask of imported maps, which takes precedence over the
+/// collision map file.
+fn collision_map_from(
+    asset_dir: &Path,
+    files: &MapFiles,
+    collision_mask: Option<GrayImage>,
+) -> Result<Map, LoadError> {
+    let chunks = chunks_path(&asset_dir.join(split_label(&files.map).0));
+    if chunks.is_file() {
+        return Map::load_chunked(&chunks);
+    }
+    match collision_mask {
+        Some(collision_mask) => Ok(Map::from_image(collision_mask)),
+        None => Map::load(&asset_dir.join(&files.collision_map)),
+    }
+}
+
+#[test]
+fn test_reinsert_entity_type() {
+    use bevy::ecs::system::CommandQueue;
+
+    let mut entity_type: EntityType =
+        serde_yaml::from_str("width: 8\nheight: 8\nimage: test.png\n").unwrap();
+    entity_type.loaded = Some(Loaded::Static(Handle::default()));
+    let overrides = EntityOverrides {
+        z_offset: 0.5,
+        ..Default::default()
+    };
+    let mut world = World::new();
+    let entity = world.spawn_empty().id();
+    let mut z = Vec::new();
+    for _ in 0..3 {
+        // Like a reload of the entity type
+        let translation = world
+            .get::<Transform>(entity)
+            .map_or(Vec3::new(0.0, 0.0, 1.0), |transform| {
+                base_translation(transform, &overrides)
+            });
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        insert_entity_type(
+            &mut commands.entity(entity),
+            &entity_type,
+            translation,
+            None,
+            &overrides,
+        );
+        queue.apply(&mut world);
+        z.push(world.get::<Transform>(entity).unwrap().translation.z);
+    }
+    assert_eq!(z, [1.5, 1.5, 1.5]);
+}
@@ -1,130 +1,65 @@
+use std::time::Duration;
+
 use bevy::{
-    ecs::system::EntityCommands,
+    asset::ChangeWatcher,
     prelude::*,
     render::camera::ScalingMode,
     window::{close_on_esc, WindowResolution},
 };
 use bevy_kira_audio::AudioPlugin;
 
-use components::{
-    animation::{Animation, AnimationState},
-    collision::Collision,
-    followcam::FollowCam,
-    interaction::Interaction,
-    player::Player,
-};
-use data::{
-    entity_types::{load_entity_types, EntityType, EntityTypes, Loaded},
-    map::load_map,
-};
-use helpers::z_index;
-use resources::{config::Config, map::Map};
-use systems::{
-    animation::{animation_system, AnimationTimer},
-    camera::camera_system,
-    input::player_input,
-    interaction::detect_interaction,
-    item::{item_bobbing, spawn_item},
-    map::initialize_map,
-    music::{music_scene, music_system},
-    player::player_system,
-    textures::{check_textures, load_textures},
+use sauerstoff::{
+    add_game_data,
+    components::{
+        followcam::FollowCam,
+        player::{Player, PLAYER_ENTITY_TYPE, PLAYER_SPAWN},
+    },
+    data::{
+        entity_types::EntityTypes,
+        map::{EntityOverrides, Map},
+    },
+    load,
+    resources::{
+        bounds::WorldBounds,
+        chunks::ChunkStreaming,
+        collision_index::CollisionIndex,
+        config::Config,
+        debug::DebugOverlay,
+        map::Map as CollisionMap,
+        navmesh::{NavMesh, NavigationRebuild},
+        pathfinding::Pathfinding,
+        transition::MapTransition,
+    },
+    spawn_entity,
+    systems::{
+        animation::animation_system,
+        camera::camera_system,
+        chunks::{stream_chunks, ChunksChanged},
+        collision::update_collision_index,
+        debug::{draw_debug_overlay, toggle_debug_overlay},
+        input::player_input,
+        interaction::detect_interaction,
+        item::{item_bobbing, spawn_item},
+        map::{initialize_map, spawn_map_background, update_world_bounds},
+        music::{music_scene, music_system},
+        navigation::{poll_path_queries, update_navigation},
+        player::player_system,
+        reload::{apply_entity_type_reloads, reload_entity_types, reload_map, PendingReloads},
+        textures::{check_textures, load_textures},
+        transition::{map_transition, use_doors},
+        trigger::{detect_triggers, TriggerEvent},
+    },
+    AppState, ImageHandles, ASSET_DIR,
 };
 
-mod components;
-mod data;
-mod helpers;
-mod resources;
-mod systems;
-
-#[derive(States, Debug, Default, Clone, PartialEq, Eq, Hash)]
-pub enum AppState {
-    #[default]
-    Setup,
-    Finished,
-}
-
-#[derive(Resource, Default)]
-pub struct ImageHandles {
-    handles: Vec<Handle<Image>>,
-}
-
-impl ImageHandles {
-    pub fn add(&mut self, handle: Handle<Image>) -> usize {
-        let index = self.handles.len();
-        self.handles.push(handle);
-        index
-    }
-}
-
-fn spawn_entity(
-    commands: &mut Commands,
-    entity_type: &EntityType,
-    mut translation: Vec3,
-    animation_name: Option<&'static str>,
-    f: fn(cmd: &mut EntityCommands),
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<Config>,
+    map: Res<Map>,
+    collision_map: Res<CollisionMap>,
+    entity_types: Res<EntityTypes>,
 ) {
-    let collision = entity_type.collision.map(|collision| {
-        let mut collision = Collision::from_data(entity_type.size, collision);
-        translation.z = collision.update_position(translation);
-        collision
-    });
-    let mut entity_cmds = match entity_type.loaded.as_ref().unwrap() {
-        Loaded::Static(handle) => commands.spawn(SpriteBundle {
-            texture: handle.clone(),
-            transform: Transform {
-                translation,
-                ..Default::default()
-            },
-            ..Default::default()
-        }),
-        Loaded::Animations(animations) => {
-            let mut cmd = commands.spawn(SpriteSheetBundle {
-                texture_atlas: animations.atlas.clone(),
-                // FIXME pass optional initial frame
-                sprite: TextureAtlasSprite {
-                    index: animation_name
-                        .map(|name| animations.frames[name][0].0)
-                        .unwrap(),
-                    ..Default::default()
-                },
-                transform: Transform {
-                    translation: Vec3::new(0.0, 0.0, z_index(0.0)),
-                    ..Default::default()
-                },
-                ..Default::default()
-            });
-            cmd.insert(Animation {
-                frames: animations.frames.clone(),
-            });
-            cmd.insert(AnimationState {
-                animation: animation_name.unwrap(),
-                restart: true,
-                index: 0,
-            });
-            cmd
-        }
-    };
-    if let Some(collision) = collision {
-        entity_cmds.insert(collision);
-    }
-    if let Some(interaction) = &entity_type.interaction {
-        entity_cmds.insert(Interaction {
-            name: interaction.name.clone(),
-            center: Vec3::new(
-                translation.x - f32::from(entity_type.size.width) / 2.0
-                    + f32::from(interaction.position.x),
-                translation.y + f32::from(entity_type.size.height) / 2.0
-                    - f32::from(interaction.position.y),
-                0.0,
-            ),
-            max_distance: interaction.max_distance,
-        });
-    }
-    f(&mut entity_cmds);
-}
-
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>, entity_types: Res<EntityTypes>) {
     commands
         .spawn({
             let mut bundle = Camera2dBundle::default();
@@ -133,24 +68,20 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>, entity_types: R
             bundle
         })
         .insert(FollowCam {});
-    commands.spawn(SpriteBundle {
-        texture: asset_server.load("map/map.jpg"),
-        transform: Transform {
-            translation: Vec3::new(0.0, 0.0, 0.0),
-            ..Default::default()
-        },
-        ..Default::default()
-    });
+    // The background of chunked maps is streamed
+    if !matches!(*collision_map, CollisionMap::Chunked(_)) {
+        spawn_map_background(&mut commands, &asset_server, &map, &config.map);
+    }
 
     spawn_entity(
         &mut commands,
-        entity_types.map.get("wolfgang").unwrap(),
-        Vec3::new(0.0, 0.0, 0.0),
+        PLAYER_ENTITY_TYPE,
+        entity_types.map.get(PLAYER_ENTITY_TYPE).unwrap(),
+        PLAYER_SPAWN,
         Some("idle"),
+        &EntityOverrides::default(),
         |cmd| {
-            cmd.insert(Player::default())
-                // XXX initial timer value?
-                .insert(AnimationTimer::from_seconds(0.1));
+            cmd.insert(Player::default());
         },
     );
 
@@ -178,40 +109,88 @@ fn resize_window(mut windows: Query<&mut Window>) {
     window.resolution = WindowResolution::new(1920.0, 1080.0);
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = Config::load();
-    let map = load_map()?;
-    let entity_types = load_entity_types()?;
+fn main() {
+    let (config, map_registry) = load().unwrap_or_else(|errors| {
+        eprint!("{}", errors);
+        std::process::exit(1);
+    });
 
     let mut app = App::new();
-    app.add_state::<AppState>();
     app.insert_resource(config);
     app.init_resource::<ImageHandles>();
-    app.init_resource::<Map>();
-    app.insert_resource(map);
-    app.insert_resource(entity_types);
-    app.add_plugins(DefaultPlugins);
+    app.insert_resource(map_registry);
+    app.init_resource::<PendingReloads>();
+    app.init_resource::<CollisionIndex>();
+    app.init_resource::<DebugOverlay>();
+    app.init_resource::<Pathfinding>();
+    app.init_resource::<NavMesh>();
+    app.init_resource::<NavigationRebuild>();
+    app.init_resource::<ChunkStreaming>();
+    app.init_resource::<WorldBounds>();
+    app.add_event::<TriggerEvent>();
+    app.add_event::<ChunksChanged>();
+    app.add_plugins(DefaultPlugins.set(AssetPlugin {
+        asset_folder: ASSET_DIR.to_owned(),
+        watch_for_changes: ChangeWatcher::with_delay(Duration::from_millis(200)),
+    }));
     app.add_plugins(AudioPlugin);
+    add_game_data(&mut app);
     app.add_systems(Startup, music_system);
     app.add_systems(Startup, resize_window);
-    app.add_systems(Startup, load_textures);
+    app.add_systems(OnEnter(AppState::Setup), load_textures);
     app.add_systems(Update, check_textures.run_if(in_state(AppState::Setup)));
     app.add_systems(OnEnter(AppState::Finished), (initialize_map, setup));
     app.add_systems(
         Update,
         (
             player_input,
-            player_system,
+            // Colliders spawned or reloaded in this frame are indexed
+            // before the player moves
+            (apply_deferred, update_collision_index)
+                .chain()
+                .after(apply_entity_type_reloads)
+                .after(reload_map)
+                .before(player_system),
+            player_system.run_if(not(resource_exists::<MapTransition>())),
+            detect_triggers
+                .after(update_collision_index)
+                .before(music_scene),
             animation_system,
             detect_interaction,
+            update_world_bounds
+                .before(player_system)
+                .before(camera_system),
             camera_system,
+            stream_chunks.after(camera_system),
             item_bobbing,
             music_scene,
+            reload_entity_types,
+            apply_entity_type_reloads,
+            reload_map,
+        )
+            .run_if(in_state(AppState::Finished)),
+    );
+    app.add_systems(
+        Update,
+        (
+            use_doors.after(detect_triggers),
+            map_transition.run_if(resource_exists::<MapTransition>()),
         )
+            .chain()
+            .run_if(in_state(AppState::Finished)),
+    );
+    app.add_systems(
+        Update,
+        (update_navigation, poll_path_queries)
+            .chain()
+            .run_if(in_state(AppState::Finished)),
+    );
+    app.add_systems(
+        Update,
+        (toggle_debug_overlay, draw_debug_overlay)
+            .chain()
             .run_if(in_state(AppState::Finished)),
     );
     app.add_systems(Update, close_on_esc);
     app.run();
-
-    Ok(())
 }
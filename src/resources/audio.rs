@@ -8,3 +8,10 @@ pub struct AudioInstances {
     pub sigh: Handle<AudioInstance>,
     pub footsteps: Handle<AudioInstance>,
 }
+
+impl AudioInstances {
+    /// Music tracks by the name used in music trigger zones
+    pub fn music(&self) -> [(&str, &Handle<AudioInstance>); 2] {
+        [("base", &self.music1), ("crystally", &self.music2)]
+    }
+}
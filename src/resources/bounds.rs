@@ -0,0 +1,101 @@
+use bevy::{ecs::system::Resource, math::Vec2};
+
+use crate::data::map::MapBounds;
+
+/// Area of the current map the camera and the player stay in. The
+/// bounds are infinite while they are unknown, e.g. while the
+/// background image is loading.
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct WorldBounds {
+    /// Lower left corner
+    pub min: Vec2,
+    /// Upper right corner
+    pub max: Vec2,
+}
+
+impl Default for WorldBounds {
+    fn default() -> Self {
+        Self {
+            min: Vec2::NEG_INFINITY,
+            max: Vec2::INFINITY,
+        }
+    }
+}
+
+impl From<MapBounds> for WorldBounds {
+    fn from(bounds: MapBounds) -> Self {
+        let center = Vec2::new(bounds.x as f32, bounds.y as f32);
+        let half_size = Vec2::new(bounds.width as f32, bounds.height as f32) / 2.0;
+        Self {
+            min: center - half_size,
+            max: center + half_size,
+        }
+    }
+}
+
+impl WorldBounds {
+    /// Bounds of the given size centered on the origin like the
+    /// background image
+    pub fn centered(size: Vec2) -> Self {
+        Self {
+            min: -size / 2.0,
+            max: size / 2.0,
+        }
+    }
+
+    /// Offset moving the rectangle given by its lower left and upper
+    /// right corner inside of the bounds. Rectangles larger than the
+    /// bounds are centered on them instead.
+    pub fn push_inside(&self, min: Vec2, max: Vec2) -> Vec2 {
+        let axis = |min: f32, max: f32, bounds_min: f32, bounds_max: f32| {
+            if max - min > bounds_max - bounds_min {
+                (bounds_min + bounds_max - min - max) / 2.0
+            } else if min < bounds_min {
+                bounds_min - min
+            } else if max > bounds_max {
+                bounds_max - max
+            } else {
+                0.0
+            }
+        };
+        Vec2::new(
+            axis(min.x, max.x, self.min.x, self.max.x),
+            axis(min.y, max.y, self.min.y, self.max.y),
+        )
+    }
+}
+
+#[test]
+fn test_push_inside() {
+    let bounds = WorldBounds::centered(Vec2::new(100.0, 50.0));
+    let push = |min: Vec2, size: Vec2| bounds.push_inside(min, min + size);
+    assert_eq!(push(Vec2::new(-10.0, -10.0), Vec2::splat(20.0)), Vec2::ZERO);
+    assert_eq!(
+        push(Vec2::new(-60.0, 10.0), Vec2::splat(20.0)),
+        Vec2::new(10.0, -5.0)
+    );
+    // Rectangles wider than the bounds are centered
+    assert_eq!(
+        push(Vec2::new(0.0, -25.0), Vec2::new(200.0, 50.0)),
+        Vec2::new(-100.0, 0.0)
+    );
+    // Unknown bounds don't move anything
+    let unbounded = WorldBounds::default();
+    assert_eq!(
+        unbounded.push_inside(Vec2::splat(-1e6), Vec2::splat(1e6)),
+        Vec2::ZERO
+    );
+    let map_bounds = MapBounds {
+        x: 10,
+        y: 0,
+        width: 20,
+        height: 10,
+    };
+    assert_eq!(
+        WorldBounds::from(map_bounds),
+        WorldBounds {
+            min: Vec2::new(0.0, -5.0),
+            max: Vec2::new(20.0, 5.0)
+        }
+    );
+}
@@ -0,0 +1,309 @@
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    ecs::system::Resource,
+    math::{Vec2, Vec3},
+    tasks::Task,
+    utils::{HashMap, HashSet},
+};
+use image::GrayImage;
+use serde::Deserialize;
+
+use crate::data::{error::LoadError, validate::read_yaml};
+
+use super::map::{read_gray_image, remove_terrain};
+
+/// Folder next to the layout file containing the chunk images
+pub const CHUNKS_DIR: &str = "chunks";
+
+/// Column and row of a chunk, starting at the upper left corner of the
+/// map
+pub type ChunkCoord = (u32, u32);
+
+/// Layout of a map whose background and collision mask are split into
+/// square chunks. Chunks at the right and bottom edge are smaller if
+/// the map size is not a multiple of the chunk size.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkLayout {
+    /// Size of the whole map in pixels
+    pub width: u32,
+    pub height: u32,
+    /// Edge length of the chunks in pixels
+    pub chunk_size: u32,
+}
+
+impl ChunkLayout {
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        let layout: Self = read_yaml(path)?;
+        if layout.width == 0 || layout.height == 0 || layout.chunk_size == 0 {
+            return Err(LoadError::syntax(
+                path,
+                "map size and chunk size must not be 0",
+            ));
+        }
+        Ok(layout)
+    }
+
+    /// Number of chunk columns and rows
+    pub fn chunk_count(&self) -> (u32, u32) {
+        (
+            self.width.div_ceil(self.chunk_size),
+            self.height.div_ceil(self.chunk_size),
+        )
+    }
+
+    /// All chunks of the map
+    pub fn chunks(&self) -> impl Iterator<Item = ChunkCoord> {
+        let (columns, rows) = self.chunk_count();
+        (0..rows).flat_map(move |y| (0..columns).map(move |x| (x, y)))
+    }
+
+    /// Size of a chunk in pixels
+    pub fn chunk_pixel_size(&self, (x, y): ChunkCoord) -> (u32, u32) {
+        (
+            self.chunk_size.min(self.width - x * self.chunk_size),
+            self.chunk_size.min(self.height - y * self.chunk_size),
+        )
+    }
+
+    /// Center of a chunk in world coordinates
+    pub fn chunk_center(&self, chunk: ChunkCoord) -> Vec2 {
+        let (width, height) = self.chunk_pixel_size(chunk);
+        let pixel = Vec2::new(
+            (chunk.0 * self.chunk_size) as f32 + width as f32 / 2.0,
+            (chunk.1 * self.chunk_size) as f32 + height as f32 / 2.0,
+        );
+        Vec2::new(
+            pixel.x - self.width as f32 / 2.0,
+            self.height as f32 / 2.0 - pixel.y,
+        )
+    }
+
+    /// Lower left and upper right corner of a chunk in world
+    /// coordinates
+    pub fn chunk_bounds(&self, chunk: ChunkCoord) -> (Vec2, Vec2) {
+        let (width, height) = self.chunk_pixel_size(chunk);
+        let half_size = Vec2::new(width as f32, height as f32) / 2.0;
+        let center = self.chunk_center(chunk);
+        (center - half_size, center + half_size)
+    }
+
+    /// Chunk at a world position or `None` if the position is outside
+    /// of the map
+    pub fn chunk_at(&self, position: Vec3) -> Option<ChunkCoord> {
+        let (x, y) = self.pixel_at(position.truncate());
+        (x >= 0.0 && y >= 0.0 && x < self.width as f32 && y < self.height as f32)
+            .then(|| (x as u32 / self.chunk_size, y as u32 / self.chunk_size))
+    }
+
+    /// Chunks overlapping the rectangle given by its lower left and
+    /// upper right corner
+    pub fn chunks_in(&self, min: Vec2, max: Vec2) -> impl Iterator<Item = ChunkCoord> {
+        // The y axis is flipped, so the upper left corner is the first
+        // pixel
+        let (first_x, first_y) = self.pixel_at(Vec2::new(min.x, max.y));
+        let (last_x, last_y) = self.pixel_at(Vec2::new(max.x, min.y));
+        let outside = last_x < 0.0
+            || last_y < 0.0
+            || first_x >= self.width as f32
+            || first_y >= self.height as f32;
+        let (columns, rows) = if outside {
+            (0..0, 0..0)
+        } else {
+            let chunk = |value: f32, size: u32| {
+                value.clamp(0.0, (size - 1) as f32) as u32 / self.chunk_size
+            };
+            (
+                chunk(first_x, self.width)..chunk(last_x, self.width) + 1,
+                chunk(first_y, self.height)..chunk(last_y, self.height) + 1,
+            )
+        };
+        rows.flat_map(move |y| columns.clone().map(move |x| (x, y)))
+    }
+
+    /// Pixel coordinates of a world position, which may be outside of
+    /// the map
+    fn pixel_at(&self, position: Vec2) -> (f32, f32) {
+        (
+            (position.x + self.width as f32 / 2.0).floor(),
+            (self.height as f32 / 2.0 - position.y).floor(),
+        )
+    }
+}
+
+/// Background image of a chunk inside the given folder
+pub fn background_chunk_file(dir: &Path, (x, y): ChunkCoord) -> PathBuf {
+    dir.join(CHUNKS_DIR)
+        .join(format!("background_{}_{}.jpg", x, y))
+}
+
+/// Collision image of a chunk inside the given folder
+pub fn collision_chunk_file(dir: &Path, (x, y): ChunkCoord) -> PathBuf {
+    dir.join(CHUNKS_DIR)
+        .join(format!("collision_{}_{}.png", x, y))
+}
+
+/// Read the collision image of a chunk and check its size
+pub fn read_collision_chunk(
+    dir: &Path,
+    layout: &ChunkLayout,
+    chunk: ChunkCoord,
+) -> Result<GrayImage, LoadError> {
+    let path = collision_chunk_file(dir, chunk);
+    let image = read_gray_image(&path)?;
+    let expected = layout.chunk_pixel_size(chunk);
+    if image.dimensions() != expected {
+        return Err(LoadError::Image {
+            path,
+            message: format!(
+                "chunk is {}x{} pixels instead of {}x{}",
+                image.width(),
+                image.height(),
+                expected.0,
+                expected.1
+            ),
+        });
+    }
+    Ok(image)
+}
+
+/// Collision image split into chunks of which only some are loaded.
+/// Pixels of chunks which are not loaded are blocked.
+#[derive(Debug, Clone)]
+pub struct ChunkedImage {
+    pub layout: ChunkLayout,
+    /// Folder containing the layout file
+    dir: PathBuf,
+    chunks: HashMap<ChunkCoord, GrayImage>,
+    /// Whether the gray levels of the chunks encode the terrain, see
+    /// [`Map::without_terrain`](super::map::Map::without_terrain)
+    pub terrain: bool,
+}
+
+impl ChunkedImage {
+    /// Read the layout file, no chunks are loaded yet
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        Ok(Self::new(
+            ChunkLayout::load(path)?,
+            path.parent().unwrap_or(Path::new("")).to_owned(),
+        ))
+    }
+
+    pub fn new(layout: ChunkLayout, dir: PathBuf) -> Self {
+        Self {
+            layout,
+            dir,
+            chunks: HashMap::default(),
+            terrain: true,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn width(&self) -> u32 {
+        self.layout.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.layout.height
+    }
+
+    /// Gray level of a pixel inside of the map, 0 if its chunk is not
+    /// loaded
+    pub fn luma(&self, x: u32, y: u32) -> u8 {
+        let size = self.layout.chunk_size;
+        self.chunks
+            .get(&(x / size, y / size))
+            .map_or(0, |chunk| chunk.get_pixel(x % size, y % size).0[0])
+    }
+
+    pub fn is_loaded(&self, chunk: ChunkCoord) -> bool {
+        self.chunks.contains_key(&chunk)
+    }
+
+    /// Loaded chunks in no particular order
+    pub fn loaded(&self) -> impl Iterator<Item = (ChunkCoord, &GrayImage)> {
+        self.chunks.iter().map(|(chunk, image)| (*chunk, image))
+    }
+
+    /// Add a chunk read by [`read_collision_chunk`]
+    pub fn insert(&mut self, chunk: ChunkCoord, mut image: GrayImage) {
+        if !self.terrain {
+            remove_terrain(&mut image);
+        }
+        self.chunks.insert(chunk, image);
+    }
+
+    pub fn remove(&mut self, chunk: ChunkCoord) {
+        self.chunks.remove(&chunk);
+    }
+
+    /// Read a chunk right away unless it is loaded already
+    pub fn load_chunk(&mut self, chunk: ChunkCoord) -> Result<(), LoadError> {
+        if !self.is_loaded(chunk) {
+            let image = read_collision_chunk(&self.dir, &self.layout, chunk)?;
+            self.insert(chunk, image);
+        }
+        Ok(())
+    }
+}
+
+/// Collision chunks which are read in the background. The tasks are
+/// dropped, which cancels them, when the chunks leave the streamed
+/// area or the map changes.
+#[derive(Resource, Default)]
+pub struct ChunkStreaming {
+    /// Folder of the chunked map the tasks belong to
+    pub dir: PathBuf,
+    pub tasks: HashMap<ChunkCoord, Task<Result<GrayImage, LoadError>>>,
+    /// Chunks which could not be read stay blocked and are not retried
+    pub failed: HashSet<ChunkCoord>,
+}
+
+#[test]
+fn test_chunked_map() {
+    use super::map::{Map, Movement};
+    use image::Luma;
+
+    // 10x6 map with 4x4 chunks, the right column and bottom row are
+    // smaller
+    let layout = ChunkLayout {
+        width: 10,
+        height: 6,
+        chunk_size: 4,
+    };
+    assert_eq!(layout.chunk_count(), (3, 2));
+    assert_eq!(layout.chunk_pixel_size((2, 1)), (2, 2));
+    assert_eq!(layout.chunk_center((0, 0)), Vec2::new(-3.0, 1.0));
+    assert_eq!(layout.chunk_center((2, 1)), Vec2::new(4.0, -2.0));
+    assert_eq!(layout.chunk_at(Vec3::new(-5.0, 3.0, 0.0)), Some((0, 0)));
+    assert_eq!(layout.chunk_at(Vec3::new(4.5, -2.5, 0.0)), Some((2, 1)));
+    assert_eq!(layout.chunk_at(Vec3::new(5.5, 0.0, 0.0)), None);
+    let visible = layout
+        .chunks_in(Vec2::new(-2.0, -1.5), Vec2::new(0.5, 100.0))
+        .collect::<Vec<_>>();
+    assert_eq!(visible, [(0, 0), (1, 0), (0, 1), (1, 1)]);
+    assert_eq!(
+        layout
+            .chunks_in(Vec2::splat(20.0), Vec2::splat(30.0))
+            .count(),
+        0
+    );
+    assert_eq!(layout.chunks().count(), 6);
+
+    // Only the upper left chunk is loaded, everything else is blocked
+    let mut chunks = ChunkedImage::new(layout, PathBuf::new());
+    chunks.insert((0, 0), GrayImage::from_pixel(4, 4, Luma([255])));
+    let map = Map::Chunked(chunks);
+    let inside = Vec3::new(-3.5, 1.5, 0.0);
+    assert!(!map.is_blocked(inside));
+    assert!(map.is_blocked(Vec3::new(0.5, 1.5, 0.0)));
+    let Movement::Clamped(position) = map.collide(inside, Vec3::new(2.5, 1.5, 0.0)) else {
+        panic!("movement into an unloaded chunk is not clamped");
+    };
+    assert!(position.x < -1.0 && !map.is_blocked(position));
+    assert!(!map.is_area_blocked(Vec2::new(-4.0, 0.0), Vec2::new(-1.0, 2.0)));
+    assert!(map.is_area_blocked(Vec2::new(-4.0, 0.0), Vec2::new(0.5, 2.0)));
+}
@@ -0,0 +1,135 @@
+use bevy::{
+    ecs::{entity::Entity, system::Resource},
+    math::Vec2,
+    utils::HashMap,
+};
+
+/// Size of the grid cells in pixels. Most colliders cover only one or
+/// a few cells.
+const CELL_SIZE: f32 = 256.0;
+
+/// First and last cell covered by a bounding box
+type CellRange = ((i32, i32), (i32, i32));
+
+/// Uniform grid of the entity colliders to find the colliders near a
+/// region without checking every collider in the world. It is kept up
+/// to date by the `update_collision_index` system.
+#[derive(Resource, Debug, Default)]
+pub struct CollisionIndex {
+    cells: HashMap<(i32, i32), Vec<Entity>>,
+    /// Cells covered by each entity
+    entities: HashMap<Entity, CellRange>,
+}
+
+impl CollisionIndex {
+    /// Add an entity with the given bounding box or move it if it is
+    /// already part of the index
+    pub fn insert(&mut self, entity: Entity, min: Vec2, max: Vec2) {
+        let Some(range) = cell_range(min, max) else {
+            self.remove(entity);
+            return;
+        };
+        if let Some(previous) = self.entities.insert(entity, range) {
+            if previous == range {
+                return;
+            }
+            self.remove_from_cells(entity, previous);
+        }
+        for cell in cells(range) {
+            self.cells.entry(cell).or_default().push(entity);
+        }
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        if let Some(range) = self.entities.remove(&entity) {
+            self.remove_from_cells(entity, range);
+        }
+    }
+
+    fn remove_from_cells(&mut self, entity: Entity, range: CellRange) {
+        for cell in cells(range) {
+            if let Some(entities) = self.cells.get_mut(&cell) {
+                entities.retain(|other| *other != entity);
+                if entities.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    /// Entities whose bounding box may overlap the given region. Every
+    /// entity is returned once.
+    pub fn query(&self, min: Vec2, max: Vec2) -> Vec<Entity> {
+        let Some(range) = cell_range(min, max) else {
+            return Vec::new();
+        };
+        let mut entities = cells(range)
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>();
+        entities.sort_unstable();
+        entities.dedup();
+        entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+/// Cells covered by a bounding box or `None` for empty or infinite
+/// boxes, e.g. of colliders without shapes
+fn cell_range(min: Vec2, max: Vec2) -> Option<CellRange> {
+    if !(min.is_finite() && max.is_finite() && min.cmple(max).all()) {
+        return None;
+    }
+    let cell = |position: Vec2| {
+        let cell = (position / CELL_SIZE).floor();
+        (cell.x as i32, cell.y as i32)
+    };
+    Some((cell(min), cell(max)))
+}
+
+fn cells(((min_x, min_y), (max_x, max_y)): CellRange) -> impl Iterator<Item = (i32, i32)> {
+    (min_y..=max_y).flat_map(move |y| (min_x..=max_x).map(move |x| (x, y)))
+}
+
+#[test]
+fn test_collision_index() {
+    let [a, b, c] = [0, 1, 2].map(Entity::from_raw);
+    let mut index = CollisionIndex::default();
+    index.insert(a, Vec2::new(0.0, 0.0), Vec2::new(10.0, 10.0));
+    // b covers several cells but is returned once
+    index.insert(b, Vec2::new(-300.0, -10.0), Vec2::new(300.0, 10.0));
+    index.insert(c, Vec2::new(1000.0, 1000.0), Vec2::new(1010.0, 1010.0));
+    index.insert(c, Vec2::new(f32::INFINITY, 0.0), Vec2::new(0.0, 0.0));
+    assert_eq!(index.len(), 2);
+    assert_eq!(
+        index.query(Vec2::new(-20.0, -20.0), Vec2::new(20.0, 20.0)),
+        vec![a, b]
+    );
+    assert_eq!(
+        index.query(Vec2::new(-290.0, 0.0), Vec2::new(-280.0, 0.0)),
+        vec![b]
+    );
+
+    // Moving and removing entities updates the cells
+    index.insert(a, Vec2::new(600.0, 600.0), Vec2::new(610.0, 610.0));
+    assert_eq!(
+        index.query(Vec2::new(-20.0, -20.0), Vec2::new(20.0, 20.0)),
+        vec![b]
+    );
+    index.remove(b);
+    assert!(index
+        .query(Vec2::new(-20.0, -20.0), Vec2::new(20.0, 20.0))
+        .is_empty());
+    assert_eq!(
+        index.query(Vec2::new(590.0, 590.0), Vec2::new(600.0, 600.0)),
+        vec![a]
+    );
+}
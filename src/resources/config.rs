@@ -1,20 +1,53 @@
+use std::path::Path;
+
 use bevy::prelude::Resource;
 use serde::Deserialize;
 
-#[derive(Resource, Debug, Deserialize)]
+use crate::data::{error::LoadError, map::MAP_FILE, validate::read_yaml};
+
+use super::map::COLLISION_MAP_FILE;
+
+#[derive(Resource, Debug, Clone, Deserialize)]
 pub struct Config {
     pub audio: AudioConfig,
+    /// Map file inside the asset folder. Besides our own `.map.yaml`
+    /// format, Tiled maps (`.tmx` and `.tmj`) and LDtk projects
+    /// (`.ldtk`) are supported. A level of an LDtk project is selected
+    /// by its identifier, e.g. `map/world.ldtk#Level_1`.
+    #[serde(default = "default_map")]
+    pub map: String,
+    /// Collision map inside the asset folder. This is either an image
+    /// where black pixels are blocked or a YAML file containing the
+    /// collision geometry. It is ignored if the map defines its own
+    /// collision mask or is streamed in chunks.
+    #[serde(default = "default_collision_map")]
+    pub collision_map: String,
+    /// Convert collision images to geometry when loading
+    #[serde(default)]
+    pub trace_collision_map: bool,
+    /// Read the terrain from the gray levels of the collision images,
+    /// see `Terrain::from_luma`. Otherwise all pixels which are not
+    /// black are walkable.
+    #[serde(default)]
+    pub collision_map_terrain: bool,
+}
+
+fn default_map() -> String {
+    MAP_FILE.to_owned()
+}
+
+fn default_collision_map() -> String {
+    COLLISION_MAP_FILE.to_owned()
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct AudioConfig {
     pub music_volume: f32,
     pub effects_volume: f32,
 }
 
 impl Config {
-    pub fn load() -> Self {
-        let file = std::fs::File::open("config.yaml").unwrap();
-        serde_yaml::from_reader(file).unwrap()
+    pub fn load() -> Result<Self, LoadError> {
+        read_yaml(Path::new("config.yaml"))
     }
 }
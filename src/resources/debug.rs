@@ -0,0 +1,11 @@
+use bevy::prelude::{KeyCode, Resource};
+
+/// Key toggling the debug overlay
+pub const DEBUG_OVERLAY_KEY: KeyCode = KeyCode::F3;
+
+/// Debug render of the collision shapes, interactions and blocked
+/// terrain
+#[derive(Resource, Debug, Default)]
+pub struct DebugOverlay {
+    pub enabled: bool,
+}
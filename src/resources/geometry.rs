@@ -0,0 +1,398 @@
+use std::path::Path;
+
+use bevy::{
+    math::{Vec2, Vec3},
+    utils::{HashMap, HashSet},
+};
+use image::GrayImage;
+use serde::Deserialize;
+
+use crate::data::error::LoadError;
+
+use super::map::Movement;
+
+/// Distance kept to walls when a movement is stopped, so the next
+/// movement doesn't start on the wall
+const WALL_DISTANCE: f32 = 0.1;
+
+/// Maximum distance in pixels between a traced outline and the pixel
+/// boundaries
+const TRACE_TOLERANCE: f32 = 0.3;
+
+/// File format of the collision geometry. Points are `[x, y]` pairs in
+/// world coordinates.
+#[derive(Deserialize, Debug, Default)]
+pub struct GeometryData {
+    #[serde(default)]
+    pub polygons: Vec<Vec<[f32; 2]>>,
+    #[serde(default)]
+    pub polylines: Vec<Vec<[f32; 2]>>,
+}
+
+/// World collision made of shapes. Polygons are outlines of walkable
+/// areas: a position is walkable if it is inside of an odd number of
+/// polygons. This way obstacles are polygons inside of a walkable area
+/// and everything outside of all polygons is blocked. Polylines are
+/// walls which can't be crossed but don't block any area.
+#[derive(Debug, Default, Clone)]
+pub struct Geometry {
+    shapes: Vec<Shape>,
+}
+
+#[derive(Debug, Clone)]
+struct Shape {
+    points: Vec<Vec2>,
+    closed: bool,
+    /// Bounding box used to skip shapes quickly
+    min: Vec2,
+    max: Vec2,
+}
+
+impl Shape {
+    fn new(points: Vec<Vec2>, closed: bool) -> Self {
+        let min = points
+            .iter()
+            .fold(Vec2::splat(f32::INFINITY), |min, point| min.min(*point));
+        let max = points
+            .iter()
+            .fold(Vec2::splat(f32::NEG_INFINITY), |max, point| max.max(*point));
+        Self {
+            points,
+            closed,
+            min,
+            max,
+        }
+    }
+
+    fn segments(&self) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        let count = if self.closed {
+            self.points.len()
+        } else {
+            self.points.len() - 1
+        };
+        (0..count).map(|i| (self.points[i], self.points[(i + 1) % self.points.len()]))
+    }
+
+    fn overlaps(&self, min: Vec2, max: Vec2) -> bool {
+        self.min.x <= max.x && self.max.x >= min.x && self.min.y <= max.y && self.max.y >= min.y
+    }
+
+    /// Check if a point is inside of a polygon by counting the edges
+    /// crossed by a ray starting at the point
+    fn contains(&self, point: Vec2) -> bool {
+        if !self.closed || !self.overlaps(point, point) {
+            return false;
+        }
+        let mut inside = false;
+        for (a, b) in self.segments() {
+            if (a.y > point.y) != (b.y > point.y)
+                && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+            {
+                inside = !inside;
+            }
+        }
+        inside
+    }
+}
+
+impl Geometry {
+    pub fn from_data(path: &Path, data: GeometryData) -> Result<Self, LoadError> {
+        let to_points =
+            |points: Vec<[f32; 2]>| points.into_iter().map(Vec2::from_array).collect::<Vec<_>>();
+        let mut shapes = Vec::new();
+        for polygon in data.polygons {
+            if polygon.len() < 3 {
+                return Err(LoadError::syntax(path, "polygons need at least 3 points"));
+            }
+            shapes.push(Shape::new(to_points(polygon), true));
+        }
+        for polyline in data.polylines {
+            if polyline.len() < 2 {
+                return Err(LoadError::syntax(path, "polylines need at least 2 points"));
+            }
+            shapes.push(Shape::new(to_points(polyline), false));
+        }
+        Ok(Self { shapes })
+    }
+
+    /// Trace the outlines of the walkable areas of a collision image
+    /// with marching squares. Pixels with the value 0 and pixels
+    /// outside of the image are blocked. The image is centered on the
+    /// origin like the background image.
+    pub fn trace(image: &GrayImage) -> Self {
+        let (width, height) = (image.width() as i32, image.height() as i32);
+        let blocked = |x: i32, y: i32| {
+            x < 0
+                || y < 0
+                || x >= width
+                || y >= height
+                || image.get_pixel(x as u32, y as u32).0[0] == 0
+        };
+        // The corners of the cells are the centers of the pixels. The
+        // outlines connect the midpoints of the cell edges which are
+        // stored in doubled pixel coordinates to keep them integers.
+        let mut neighbors: HashMap<(i32, i32), Vec<(i32, i32)>> = HashMap::default();
+        for y in 0..=height {
+            for x in 0..=width {
+                let corners = [
+                    blocked(x - 1, y - 1),
+                    blocked(x, y - 1),
+                    blocked(x, y),
+                    blocked(x - 1, y),
+                ];
+                let top = (2 * x, 2 * y - 1);
+                let right = (2 * x + 1, 2 * y);
+                let bottom = (2 * x, 2 * y + 1);
+                let left = (2 * x - 1, 2 * y);
+                let segments = match corners {
+                    // Saddles are resolved by separating the blocked
+                    // corners
+                    [true, false, true, false] => vec![(top, left), (bottom, right)],
+                    [false, true, false, true] => vec![(top, right), (bottom, left)],
+                    [top_left, top_right, bottom_right, bottom_left] => {
+                        let crossed = [
+                            (top_left != top_right, top),
+                            (top_right != bottom_right, right),
+                            (bottom_right != bottom_left, bottom),
+                            (bottom_left != top_left, left),
+                        ]
+                        .into_iter()
+                        .filter(|(crossed, _)| *crossed)
+                        .map(|(_, point)| point)
+                        .collect::<Vec<_>>();
+                        match crossed[..] {
+                            [a, b] => vec![(a, b)],
+                            _ => Vec::new(),
+                        }
+                    }
+                };
+                for (a, b) in segments {
+                    neighbors.entry(a).or_default().push(b);
+                    neighbors.entry(b).or_default().push(a);
+                }
+            }
+        }
+
+        // Every midpoint has exactly two neighbors, so the segments form
+        // closed loops
+        let to_world = |(x, y): (i32, i32)| {
+            Vec2::new(
+                x as f32 / 2.0 - width as f32 / 2.0,
+                height as f32 / 2.0 - y as f32 / 2.0,
+            )
+        };
+        let mut visited = HashSet::default();
+        let mut shapes = Vec::new();
+        for &start in neighbors.keys() {
+            if !visited.insert(start) {
+                continue;
+            }
+            let mut points = vec![to_world(start)];
+            let (mut previous, mut current) = (start, neighbors[&start][0]);
+            while current != start {
+                visited.insert(current);
+                points.push(to_world(current));
+                let next = neighbors[&current]
+                    .iter()
+                    .copied()
+                    .find(|point| *point != previous)
+                    .unwrap();
+                (previous, current) = (current, next);
+            }
+            let points = simplify(&points, TRACE_TOLERANCE);
+            if points.len() >= 3 {
+                shapes.push(Shape::new(points, true));
+            }
+        }
+        Self { shapes }
+    }
+
+    /// Points of all polygons and polylines and whether the shape is
+    /// closed
+    pub fn outlines(&self) -> impl Iterator<Item = (&[Vec2], bool)> {
+        self.shapes
+            .iter()
+            .map(|shape| (shape.points.as_slice(), shape.closed))
+    }
+
+    /// Lower left and upper right corner of the bounding box of all
+    /// shapes
+    pub fn bounds(&self) -> (Vec2, Vec2) {
+        self.shapes.iter().fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), shape| (min.min(shape.min), max.max(shape.max)),
+        )
+    }
+
+    /// Check if any part of the rectangle is blocked or crossed by a
+    /// wall
+    pub fn is_area_blocked(&self, min: Vec2, max: Vec2) -> bool {
+        let corners = [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)];
+        let inside = |point: Vec2| point.cmpge(min).all() && point.cmple(max).all();
+        self.is_blocked(((min + max) / 2.0).extend(0.0))
+            || self
+                .shapes
+                .iter()
+                .filter(|shape| shape.overlaps(min, max))
+                .flat_map(|shape| shape.segments())
+                .any(|(a, b)| {
+                    inside(a)
+                        || (0..4)
+                            .any(|i| intersect(a, b, corners[i], corners[(i + 1) % 4]).is_some())
+                })
+    }
+
+    pub fn is_blocked(&self, position: Vec3) -> bool {
+        let point = position.truncate();
+        self.shapes
+            .iter()
+            .filter(|shape| shape.contains(point))
+            .count()
+            % 2
+            == 0
+    }
+
+    /// Find the far most non-colliding position on the line from
+    /// source to target
+    pub fn collide(&self, source: Vec3, target: Vec3) -> Movement {
+        if self.is_blocked(source) {
+            return Movement::StartedInside;
+        }
+        let (start, end) = (source.truncate(), target.truncate());
+        let Some(hit) = self.first_hit(start, end) else {
+            return Movement::Free;
+        };
+        let direction = end - start;
+        let hit = (hit - WALL_DISTANCE / direction.length()).max(0.0);
+        Movement::Clamped((start + direction * hit).extend(target.z))
+    }
+
+    /// Find the first position on the line from source to target which
+    /// hits a wall. Lines starting on a blocked position hit at the
+    /// source.
+    pub fn raycast(&self, source: Vec3, target: Vec3) -> Option<Vec3> {
+        if self.is_blocked(source) {
+            return Some(source);
+        }
+        self.first_hit(source.truncate(), target.truncate())
+            .map(|hit| source.lerp(target, hit))
+    }
+
+    /// Position of the first wall crossed by the line from 0 (`start`)
+    /// to 1 (`end`)
+    fn first_hit(&self, start: Vec2, end: Vec2) -> Option<f32> {
+        let (min, max) = (start.min(end), start.max(end));
+        self.shapes
+            .iter()
+            .filter(|shape| shape.overlaps(min, max))
+            .flat_map(|shape| shape.segments())
+            .filter_map(|(a, b)| intersect(start, end, a, b))
+            .reduce(f32::min)
+    }
+}
+
+/// Intersection of the lines `start` to `end` and `a` to `b`. The
+/// result is the position on the first line from 0 (`start`) to 1
+/// (`end`).
+pub fn intersect(start: Vec2, end: Vec2, a: Vec2, b: Vec2) -> Option<f32> {
+    let (direction, edge) = (end - start, b - a);
+    let denominator = direction.perp_dot(edge);
+    if denominator == 0.0 {
+        // Parallel lines
+        return None;
+    }
+    let t = (a - start).perp_dot(edge) / denominator;
+    let u = (a - start).perp_dot(direction) / denominator;
+    ((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)).then_some(t)
+}
+
+/// Reduce the number of points of a closed outline with the
+/// Douglas-Peucker algorithm
+fn simplify(points: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    // The outline is split at the point farthest away from the first
+    // point and both halves are simplified separately
+    let farthest = (1..points.len())
+        .max_by(|a, b| {
+            points[0]
+                .distance_squared(points[*a])
+                .total_cmp(&points[0].distance_squared(points[*b]))
+        })
+        .unwrap_or(0);
+    if farthest == 0 {
+        return points.to_vec();
+    }
+    let mut result = douglas_peucker(&points[..=farthest], tolerance);
+    result.pop();
+    let mut second_half = points[farthest..].to_vec();
+    second_half.push(points[0]);
+    result.extend(douglas_peucker(&second_half, tolerance));
+    result.pop();
+    result
+}
+
+/// Simplify a line keeping its first and last point
+fn douglas_peucker(points: &[Vec2], tolerance: f32) -> Vec<Vec2> {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let farthest = (1..points.len() - 1)
+        .map(|i| (i, distance_to_segment(points[i], first, last)))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b));
+    match farthest {
+        Some((i, distance)) if distance > tolerance => {
+            let mut result = douglas_peucker(&points[..=i], tolerance);
+            result.pop();
+            result.extend(douglas_peucker(&points[i..], tolerance));
+            result
+        }
+        _ => vec![first, last],
+    }
+}
+
+fn distance_to_segment(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let segment = b - a;
+    let length_squared = segment.length_squared();
+    if length_squared == 0.0 {
+        return point.distance(a);
+    }
+    let t = ((point - a).dot(segment) / length_squared).clamp(0.0, 1.0);
+    point.distance(a + segment * t)
+}
+
+#[test]
+fn test_geometry() {
+    use image::Luma;
+
+    // Walkable 8x6 image with a 2x2 obstacle
+    let mut image = GrayImage::from_pixel(8, 6, Luma([255]));
+    for (x, y) in [(3, 2), (4, 2), (3, 3), (4, 3)] {
+        image.put_pixel(x, y, Luma([0]));
+    }
+    let geometry = Geometry::trace(&image);
+    assert_eq!(geometry.shapes.len(), 2);
+    for y in 0..6 {
+        for x in 0..8 {
+            // Pixel centers in world coordinates
+            let position = Vec3::new(x as f32 - 3.5, 2.5 - y as f32, 0.0);
+            assert_eq!(
+                geometry.is_blocked(position),
+                image.get_pixel(x, y).0[0] == 0,
+                "{}:{}",
+                x,
+                y
+            );
+        }
+    }
+    assert!(geometry.is_blocked(Vec3::new(10.0, 0.0, 0.0)));
+
+    // Walking into the obstacle from the left stops in front of it
+    let Movement::Clamped(stop) =
+        geometry.collide(Vec3::new(-3.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.0))
+    else {
+        panic!("movement into the obstacle is not clamped");
+    };
+    assert!(stop.x < -1.0 && stop.x > -1.6);
+    assert!(!geometry.is_blocked(stop));
+    assert_eq!(
+        geometry.collide(Vec3::new(-3.0, 2.0, 0.0), Vec3::new(2.0, 2.0, 0.0)),
+        Movement::Free
+    );
+}
@@ -1,65 +1,515 @@
+use std::{f32::consts::TAU, iter, path::Path};
+
 use bevy::{
     ecs::system::Resource,
-    math::{Mat4, Quat, Vec3},
+    math::{Mat4, Quat, Vec2, Vec3},
 };
 use bresenham::Bresenham;
 use image::GrayImage;
 
-#[derive(Resource)]
-pub struct Map {
-    collision_map: GrayImage,
+use crate::data::{error::LoadError, validate::read_yaml};
+
+use super::{
+    chunks::ChunkedImage,
+    geometry::{Geometry, GeometryData},
+};
+
+/// Collision of the world
+#[derive(Resource, Clone)]
+pub enum Map {
+    /// Image where blocked pixels have the value 0. The gray levels of
+    /// the other pixels encode the terrain, see [`Terrain::from_luma`].
+    /// Images without terrain are converted by [`Map::without_terrain`].
+    Image(GrayImage),
+    Geometry(Geometry),
+    /// Image streamed in chunks around the camera. Chunks which are not
+    /// loaded are blocked.
+    Chunked(ChunkedImage),
+}
+
+/// Result of a movement query against the world collision
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Movement {
+    /// Nothing blocks the path to the target
+    Free,
+    /// The movement is blocked and ends at the contained position
+    Clamped(Vec3),
+    /// The movement starts on a blocked position, e.g. because the map
+    /// changed below the entity
+    StartedInside,
 }
 
-impl Default for Map {
-    fn default() -> Self {
-        // FIXME this image loading is kinda inefficient
-        let reader = image::io::Reader::open("assets/map/map-collision.png").unwrap();
-        let img = reader.decode().unwrap();
-        let img = img.into_luma8();
-        Self { collision_map: img }
+/// Surface of the world at a position
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Terrain {
+    Blocked,
+    #[default]
+    Walkable,
+    /// Slows movement down
+    Mud,
+    /// Shallow water which slows movement down a bit
+    Water,
+    /// Zone without oxygen
+    Hazard,
+}
+
+impl Terrain {
+    /// Classify a gray level of the collision image. Ranges are used
+    /// instead of exact values so smoothed edges keep their terrain.
+    ///
+    /// - 0: blocked
+    /// - around 64: hazard
+    /// - around 128: shallow water
+    /// - around 192: mud
+    /// - 224 and above: walkable
+    pub fn from_luma(value: u8) -> Self {
+        match value {
+            0 => Self::Blocked,
+            1..=95 => Self::Hazard,
+            96..=159 => Self::Water,
+            160..=223 => Self::Mud,
+            224..=255 => Self::Walkable,
+        }
+    }
+
+    /// Factor applied to the movement speed on this terrain
+    pub fn speed_factor(self) -> f32 {
+        match self {
+            Self::Blocked => 0.0,
+            Self::Walkable | Self::Hazard => 1.0,
+            Self::Mud => 0.5,
+            Self::Water => 0.7,
+        }
     }
 }
 
+/// Replace the gray level of all pixels which are not blocked by the
+/// one of walkable terrain
+pub fn remove_terrain(image: &mut GrayImage) {
+    for pixel in image.pixels_mut() {
+        if pixel.0[0] != 0 {
+            pixel.0[0] = u8::MAX;
+        }
+    }
+}
+
+/// Maximum distance in pixels an entity is pushed out of blocking
+/// terrain
+const MAX_PUSH_DISTANCE: f32 = 64.0;
+
+/// Path of the collision map inside the asset folder which is used if
+/// no collision map is configured. It is ignored if the map defines
+/// its own collision mask.
+pub const COLLISION_MAP_FILE: &str = "map/map-collision.png";
+
 impl Map {
-    /// Find the far most non-colliding position on the map for a given
-    /// target coordinate coming from a given source coordinate.
-    pub fn collide(&self, source: Vec3, target: Vec3) -> Option<Vec3> {
-        let img_width: u32 = self.collision_map.width();
-        let img_height: u32 = self.collision_map.height();
-        let mat = Mat4::from_scale_rotation_translation(
-            Vec3::new(1.0, -1.0, 1.0),
-            Quat::IDENTITY,
-            Vec3::new((img_width as f32) / 2.0, (img_height as f32) / 2.0, 0.0),
-        );
-        let img_target = mat.transform_point3(target);
-        let img_target = (img_target.x as isize, img_target.y as isize);
-        // The bresenham algorithm does not yield the last coordinate.
-        // We skip the entire algorithm if the target coordinate is
-        // non-blocking. One fair warning: This allows skipping over
-        // blocking-terrain if the movement speed is high enough and
-        // skips that blocking terrain in one frame.
-        if self
-            .collision_map
-            .get_pixel(
-                img_target.0.try_into().unwrap(),
-                img_target.1.try_into().unwrap(),
-            )
-            .0[0]
-            > 0
+    /// Load a collision image or, for YAML files, the collision
+    /// geometry
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "yaml")
+        {
+            let data: GeometryData = read_yaml(path)?;
+            return Ok(Self::Geometry(Geometry::from_data(path, data)?));
+        }
+        read_gray_image(path).map(Self::from_image)
+    }
+
+    /// Read the layout of a chunked map, see [`ChunkedImage`]. No
+    /// chunks are loaded.
+    pub fn load_chunked(path: &Path) -> Result<Self, LoadError> {
+        ChunkedImage::load(path).map(Self::Chunked)
+    }
+
+    /// Create the collision map from a mask where blocked pixels are 0
+    pub fn from_image(collision_map: GrayImage) -> Self {
+        Self::Image(collision_map)
+    }
+
+    /// Make all pixels of a collision image walkable which are not
+    /// blocked. This is used for collision images which don't encode
+    /// the terrain in their gray levels.
+    pub fn without_terrain(self) -> Self {
+        match self {
+            Self::Image(mut image) => {
+                remove_terrain(&mut image);
+                Self::Image(image)
+            }
+            Self::Chunked(mut chunks) => {
+                chunks.terrain = false;
+                Self::Chunked(chunks)
+            }
+            Self::Geometry(_) => self,
+        }
+    }
+
+    /// Convert a collision image to geometry. Chunked images are kept
+    /// as they are never complete.
+    pub fn traced(self) -> Self {
+        match self {
+            Self::Image(image) => Self::Geometry(Geometry::trace(&image)),
+            Self::Geometry(_) | Self::Chunked(_) => self,
+        }
+    }
+
+    /// Check if the given position is blocked. Positions outside of
+    /// the collision map are always blocked.
+    pub fn is_blocked(&self, position: Vec3) -> bool {
+        self.terrain_at(position) == Terrain::Blocked
+    }
+
+    /// Lower left and upper right corner of the area covered by the map
+    pub fn bounds(&self) -> (Vec2, Vec2) {
+        match self {
+            Self::Image(collision_map) => {
+                let half_size =
+                    Vec2::new(collision_map.width() as f32, collision_map.height() as f32) / 2.0;
+                (-half_size, half_size)
+            }
+            Self::Geometry(geometry) => {
+                let (min, max) = geometry.bounds();
+                if min.is_finite() && max.is_finite() {
+                    (min, max)
+                } else {
+                    (Vec2::ZERO, Vec2::ZERO)
+                }
+            }
+            Self::Chunked(chunks) => {
+                let half_size = Vec2::new(chunks.width() as f32, chunks.height() as f32) / 2.0;
+                (-half_size, half_size)
+            }
+        }
+    }
+
+    /// Check if any part of the rectangle given by its lower left and
+    /// upper right corner is blocked
+    pub fn is_area_blocked(&self, min: Vec2, max: Vec2) -> bool {
+        let collision_map: &dyn CollisionImage = match self {
+            Self::Image(collision_map) => collision_map,
+            Self::Chunked(chunks) => chunks,
+            Self::Geometry(geometry) => return geometry.is_area_blocked(min, max),
+        };
+        let mat = to_image(collision_map);
+        // The y axis is flipped, so the upper left corner is the first
+        // pixel
+        let first = mat.transform_point3(Vec3::new(min.x, max.y, 0.0)).floor();
+        let last = mat.transform_point3(Vec3::new(max.x, min.y, 0.0)).ceil() - 1.0;
+        if first.x < 0.0
+            || first.y < 0.0
+            || last.x >= collision_map.width() as f32
+            || last.y >= collision_map.height() as f32
         {
-            return None;
+            return true;
+        }
+        (first.y as u32..=last.y as u32)
+            .any(|y| (first.x as u32..=last.x as u32).any(|x| collision_map.luma(x, y) == 0))
+    }
+
+    /// Get the terrain at the given position. Collision geometry has
+    /// no terrain information, so every free position is walkable.
+    pub fn terrain_at(&self, position: Vec3) -> Terrain {
+        let collision_map: &dyn CollisionImage = match self {
+            Self::Image(collision_map) => collision_map,
+            Self::Chunked(chunks) => chunks,
+            Self::Geometry(geometry) if geometry.is_blocked(position) => return Terrain::Blocked,
+            Self::Geometry(_) => return Terrain::Walkable,
+        };
+        pixel_at(collision_map, position).map_or(Terrain::Blocked, |(x, y)| {
+            Terrain::from_luma(collision_map.luma(x, y))
+        })
+    }
+
+    /// Find the position reached when moving from source to target.
+    /// Movements blocked by a wall are split into their horizontal and
+    /// vertical part, so entities slide along walls instead of
+    /// stopping.
+    pub fn collide(&self, source: Vec3, target: Vec3) -> Movement {
+        let stop = match self.collide_line(source, target) {
+            Movement::Clamped(stop) => stop,
+            movement => return movement,
+        };
+        let reach = |from: Vec3, to: Vec3| match self.collide_line(from, to) {
+            Movement::Free => to,
+            Movement::Clamped(position) => position,
+            Movement::StartedInside => from,
+        };
+        // Move the rest of the way along one axis after the other,
+        // starting where the direct movement stopped. Both orders are
+        // tried as the first movement may block the second one.
+        let slide = |horizontal_first: bool| {
+            let first = if horizontal_first {
+                Vec3::new(target.x, stop.y, target.z)
+            } else {
+                Vec3::new(stop.x, target.y, target.z)
+            };
+            let position = reach(stop, first);
+            let second = if horizontal_first {
+                Vec3::new(position.x, target.y, target.z)
+            } else {
+                Vec3::new(target.x, position.y, target.z)
+            };
+            reach(position, second)
+        };
+        let position = [slide(true), slide(false), stop]
+            .into_iter()
+            .min_by(|a, b| {
+                a.truncate()
+                    .distance_squared(target.truncate())
+                    .total_cmp(&b.truncate().distance_squared(target.truncate()))
+            })
+            .unwrap();
+        Movement::Clamped(position)
+    }
+
+    /// Find the nearest position which is not blocked by searching
+    /// circles with growing radius around the given position. Used to
+    /// push entities out of blocking terrain.
+    pub fn nearest_free(&self, position: Vec3) -> Option<Vec3> {
+        if !self.is_blocked(position) {
+            return Some(position);
         }
-        let img_source = mat.transform_point3(source);
-        let img_source = (img_source.x as isize, img_source.y as isize);
-        // FIXME make sure x and y aren't completely out of bounds
-        let last_non_colliding = Bresenham::new(img_source, img_target)
-            .take_while(|(x, y)| self.collision_map.get_pixel(*x as u32, *y as u32).0[0] > 0)
-            .last();
-        // We should probably return an error if the player managed to wander in
-        // blocking territory.
-        last_non_colliding.map(|p| {
-            mat.inverse()
-                .transform_point3(Vec3::new(p.0 as f32, p.1 as f32, 0.0))
+        (1..=MAX_PUSH_DISTANCE as u32).find_map(|radius| {
+            let radius = radius as f32;
+            // Roughly one sample per pixel of the circumference
+            let samples = (TAU * radius).ceil() as u32;
+            (0..samples)
+                .map(|i| {
+                    let angle = i as f32 / samples as f32 * TAU;
+                    position + Vec3::new(angle.cos(), angle.sin(), 0.0) * radius
+                })
+                .find(|candidate| !self.is_blocked(*candidate))
         })
     }
+
+    /// Find the far most non-colliding position on the map for a given
+    /// target coordinate coming from a given source coordinate. The
+    /// whole path is traced, so fast movements can't skip over walls.
+    fn collide_line(&self, source: Vec3, target: Vec3) -> Movement {
+        let collision_map: &dyn CollisionImage = match self {
+            Self::Image(collision_map) => collision_map,
+            Self::Chunked(chunks) => chunks,
+            Self::Geometry(geometry) => return geometry.collide(source, target),
+        };
+        let mat = to_image(collision_map);
+        if is_blocked_pixel(collision_map, image_pixel(&mat, source)) {
+            return Movement::StartedInside;
+        }
+        let Some((blocked_step, steps)) = trace_line(collision_map, source, target) else {
+            return Movement::Free;
+        };
+        // Stop on the line in front of the first blocked pixel. The
+        // source pixel is free, so blocked_step is at least 1.
+        let position = (0..blocked_step)
+            .rev()
+            .map(|step| source.lerp(target, step as f32 / steps as f32))
+            .find(|position| !is_blocked_pixel(collision_map, image_pixel(&mat, *position)))
+            .unwrap_or(source);
+        Movement::Clamped(position)
+    }
+
+    /// Find the first blocked position on the line from source to
+    /// target or `None` if the whole line is free. Lines starting on a
+    /// blocked position hit at the source.
+    pub fn raycast(&self, source: Vec3, target: Vec3) -> Option<Vec3> {
+        let collision_map: &dyn CollisionImage = match self {
+            Self::Image(collision_map) => collision_map,
+            Self::Chunked(chunks) => chunks,
+            Self::Geometry(geometry) => return geometry.raycast(source, target),
+        };
+        let (blocked_step, steps) = trace_line(collision_map, source, target)?;
+        Some(source.lerp(target, blocked_step as f32 / steps.max(1) as f32))
+    }
+}
+
+/// Trace the pixels of the line from source to target. The result is
+/// the step of the first blocked pixel, where the source pixel is step
+/// 0, and the number of steps of the whole line.
+fn trace_line(
+    collision_map: &dyn CollisionImage,
+    source: Vec3,
+    target: Vec3,
+) -> Option<(usize, usize)> {
+    let mat = to_image(collision_map);
+    let (img_source, img_target) = (image_pixel(&mat, source), image_pixel(&mat, target));
+    // The bresenham algorithm does not yield the last coordinate but
+    // every pixel of the path has to be checked
+    let blocked_step = Bresenham::new(img_source, img_target)
+        .chain(iter::once(img_target))
+        .position(|pixel| is_blocked_pixel(collision_map, pixel))?;
+    let steps = (img_target.0 - img_source.0)
+        .abs()
+        .max((img_target.1 - img_source.1).abs());
+    Some((blocked_step, steps as usize))
+}
+
+/// Pixel coordinates of a world position, which may be outside of the
+/// collision map
+fn image_pixel(to_image: &Mat4, position: Vec3) -> (isize, isize) {
+    let position = to_image.transform_point3(position).floor();
+    (position.x as isize, position.y as isize)
+}
+
+/// Check if a pixel is blocked. Pixels outside of the collision map are
+/// always blocked.
+fn is_blocked_pixel(collision_map: &dyn CollisionImage, (x, y): (isize, isize)) -> bool {
+    x < 0
+        || y < 0
+        || x >= collision_map.width() as isize
+        || y >= collision_map.height() as isize
+        || collision_map.luma(x as u32, y as u32) == 0
+}
+
+/// Pixel of the collision map at a world position or `None` if the
+/// position is outside of the map
+fn pixel_at(collision_map: &dyn CollisionImage, position: Vec3) -> Option<(u32, u32)> {
+    let img_position = to_image(collision_map).transform_point3(position);
+    let (x, y) = (img_position.x.floor(), img_position.y.floor());
+    (x >= 0.0 && y >= 0.0 && x < collision_map.width() as f32 && y < collision_map.height() as f32)
+        .then_some((x as u32, y as u32))
+}
+
+/// Transformation from world coordinates to collision map pixels
+fn to_image(collision_map: &dyn CollisionImage) -> Mat4 {
+    let img_width: u32 = collision_map.width();
+    let img_height: u32 = collision_map.height();
+    Mat4::from_scale_rotation_translation(
+        Vec3::new(1.0, -1.0, 1.0),
+        Quat::IDENTITY,
+        Vec3::new((img_width as f32) / 2.0, (img_height as f32) / 2.0, 0.0),
+    )
+}
+
+/// Read an image and convert it to gray levels
+pub fn read_gray_image(path: &Path) -> Result<GrayImage, LoadError> {
+    // FIXME this image loading is kinda inefficient
+    let reader = image::io::Reader::open(path).map_err(|e| LoadError::io(path, e))?;
+    let img = reader.decode().map_err(|e| LoadError::Image {
+        path: path.to_owned(),
+        message: e.to_string(),
+    })?;
+    Ok(img.into_luma8())
+}
+
+/// Gray levels of a collision image, which is either kept in memory as
+/// a whole or streamed in chunks
+trait CollisionImage {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+    /// Gray level of a pixel inside of the image
+    fn luma(&self, x: u32, y: u32) -> u8;
+}
+
+impl CollisionImage for GrayImage {
+    fn width(&self) -> u32 {
+        self.dimensions().0
+    }
+    fn height(&self) -> u32 {
+        self.dimensions().1
+    }
+    fn luma(&self, x: u32, y: u32) -> u8 {
+        self.get_pixel(x, y).0[0]
+    }
+}
+
+impl CollisionImage for ChunkedImage {
+    fn width(&self) -> u32 {
+        self.layout.width
+    }
+    fn height(&self) -> u32 {
+        self.layout.height
+    }
+    fn luma(&self, x: u32, y: u32) -> u8 {
+        ChunkedImage::luma(self, x, y)
+    }
+}
+
+#[test]
+fn test_wall_sliding() {
+    use image::Luma;
+
+    // Walkable 8x6 image with a blocked column on the right
+    let mut image = GrayImage::from_pixel(8, 6, Luma([255]));
+    for y in 0..6 {
+        image.put_pixel(6, y, Luma([0]));
+    }
+    for map in [
+        Map::from_image(image.clone()),
+        Map::from_image(image).traced(),
+    ] {
+        // Walking diagonally into the wall keeps the vertical movement
+        let Movement::Clamped(position) =
+            map.collide(Vec3::new(1.0, 0.0, 0.0), Vec3::new(2.5, 2.0, 0.0))
+        else {
+            panic!("movement into the wall is not clamped");
+        };
+        assert!(position.x > 0.9 && position.x < 2.0);
+        assert!((position.y - 2.0).abs() < 0.01);
+    }
+}
+
+#[test]
+fn test_swept_collision() {
+    use image::Luma;
+
+    // Walkable 8x6 image with a blocked column in the middle
+    let mut image = GrayImage::from_pixel(8, 6, Luma([255]));
+    for y in 0..6 {
+        image.put_pixel(4, y, Luma([0]));
+    }
+    for map in [
+        Map::from_image(image.clone()),
+        Map::from_image(image).traced(),
+    ] {
+        // Jumping over the wall in one step is not possible
+        let Movement::Clamped(position) =
+            map.collide(Vec3::new(-2.5, 0.0, 0.0), Vec3::new(2.5, 0.0, 0.0))
+        else {
+            panic!("movement over the wall is not clamped");
+        };
+        assert!(position.x < 0.0);
+        // Leaving the map is blocked and doesn't panic
+        let Movement::Clamped(position) =
+            map.collide(Vec3::new(-2.5, 0.0, 0.0), Vec3::new(-100.0, 0.0, 0.0))
+        else {
+            panic!("movement out of the map is not clamped");
+        };
+        assert!(!map.is_blocked(position));
+        assert_eq!(
+            map.collide(Vec3::new(-2.5, 0.0, 0.0), Vec3::new(-1.5, 1.0, 0.0)),
+            Movement::Free
+        );
+        // Entities stuck in the wall are pushed out
+        let stuck = Vec3::new(0.5, 0.0, 0.0);
+        assert_eq!(
+            map.collide(stuck, Vec3::new(1.0, 0.0, 0.0)),
+            Movement::StartedInside
+        );
+        let free = map.nearest_free(stuck).unwrap();
+        assert!(!map.is_blocked(free));
+        assert!(free.distance(stuck) <= 1.0);
+    }
+}
+
+#[test]
+fn test_terrain() {
+    use image::Luma;
+
+    let image = GrayImage::from_fn(4, 1, |x, _| Luma([[0, 64, 190, 255][x as usize]]));
+    let map = Map::from_image(image);
+    let terrains = [-1.5, -0.5, 0.5, 1.5, 2.5].map(|x| map.terrain_at(Vec3::new(x, 0.0, 0.0)));
+    assert_eq!(
+        terrains,
+        [
+            Terrain::Blocked,
+            Terrain::Hazard,
+            Terrain::Mud,
+            Terrain::Walkable,
+            Terrain::Blocked
+        ]
+    );
+    assert!(!map.is_blocked(Vec3::new(-0.5, 0.0, 0.0)));
+    let map = map.without_terrain();
+    assert_eq!(map.terrain_at(Vec3::new(-0.5, 0.0, 0.0)), Terrain::Walkable);
+    assert_eq!(map.terrain_at(Vec3::new(-1.5, 0.0, 0.0)), Terrain::Blocked);
 }
@@ -0,0 +1,108 @@
+use std::path::Path;
+
+use bevy::{prelude::Resource, utils::HashMap};
+
+use crate::data::{
+    error::{LoadError, LoadErrors},
+    map::split_label,
+};
+
+use super::config::Config;
+
+/// Folder inside the asset folder containing one folder per map
+pub const MAPS_DIR: &str = "maps";
+
+/// Map file inside the folder of a map
+pub const MAP_FILE_NAME: &str = "entities.map.yaml";
+
+/// Collision maps inside the folder of a map, the first existing one
+/// is used
+const COLLISION_MAP_FILE_NAMES: [&str; 2] = ["map-collision.yaml", "map-collision.png"];
+
+/// Files of a map relative to the asset folder
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapFiles {
+    pub map: String,
+    /// Ignored if the map defines its own collision mask
+    pub collision_map: String,
+}
+
+/// All maps the player can travel between through doors. Maps are
+/// named after their folder, so the map in `maps/spaceship/` is called
+/// `spaceship`. The configured start map is registered as well.
+#[derive(Resource, Debug, Default)]
+pub struct MapRegistry {
+    pub maps: HashMap<String, MapFiles>,
+}
+
+impl MapRegistry {
+    /// Register the start map and all folders in the maps folder.
+    /// Folders which can't be read are added to `errors`.
+    pub fn scan(asset_dir: &Path, config: &Config, errors: &mut LoadErrors) -> Self {
+        let mut maps = HashMap::default();
+        maps.insert(
+            map_name(&config.map).to_owned(),
+            MapFiles {
+                map: config.map.clone(),
+                collision_map: config.collision_map.clone(),
+            },
+        );
+        let dir = asset_dir.join(MAPS_DIR);
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries.collect::<Vec<_>>(),
+            // Games with a single map don't need the maps folder
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                errors.push(LoadError::io(&dir, e));
+                Vec::new()
+            }
+        };
+        for entry in entries {
+            let Some(entry) = errors.collect(entry.map_err(|e| LoadError::io(&dir, e))) else {
+                continue;
+            };
+            let path = entry.path();
+            if !path.is_dir() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let map_dir = format!("{}/{}", MAPS_DIR, name);
+            let collision_map = COLLISION_MAP_FILE_NAMES
+                .iter()
+                .find(|file| path.join(file).is_file())
+                .unwrap_or(&COLLISION_MAP_FILE_NAMES[0]);
+            maps.insert(
+                name,
+                MapFiles {
+                    map: format!("{}/{}", map_dir, MAP_FILE_NAME),
+                    collision_map: format!("{}/{}", map_dir, collision_map),
+                },
+            );
+        }
+        Self { maps }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&MapFiles> {
+        self.maps.get(name)
+    }
+}
+
+/// Name of the map in the given map file, which is the name of its
+/// folder
+pub fn map_name(map_file: &str) -> &str {
+    let (map_file, _) = split_label(map_file);
+    map_file
+        .rsplit_once('/')
+        .map_or("", |(dir, _)| dir)
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+}
+
+#[test]
+fn test_map_name() {
+    assert_eq!(map_name("map/entities.map.yaml"), "map");
+    assert_eq!(map_name("maps/spaceship/entities.map.yaml"), "spaceship");
+    assert_eq!(map_name("map/world.ldtk#Level_1"), "map");
+    assert_eq!(map_name("entities.map.yaml"), "");
+}
@@ -1,3 +1,13 @@
 pub mod audio;
+pub mod bounds;
+pub mod chunks;
+pub mod collision_index;
 pub mod config;
+pub mod debug;
+pub mod geometry;
 pub mod map;
+pub mod map_registry;
+pub mod navmesh;
+pub mod pathfinding;
+pub mod raycast;
+pub mod transition;
@@ -0,0 +1,503 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    ecs::system::Resource,
+    math::{Vec2, Vec3},
+    tasks::Task,
+    utils::HashSet,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::data::{error::LoadError, map::split_label, validate::read_json};
+
+use super::{chunks::ChunkCoord, pathfinding::NavGrid};
+
+/// Radius of the agents the navigation mesh is baked for
+pub const NAV_MESH_AGENT_RADIUS: f32 = 24.0;
+
+/// Extension of baked navigation meshes, which are stored next to the
+/// map file by `sauerstoff-bake`
+pub const NAV_MESH_EXTENSION: &str = "navmesh.json";
+
+/// Changing the baking invalidates all baked navigation meshes
+const NAV_MESH_VERSION: u32 = 3;
+
+/// Path of the baked navigation mesh of a map file, e.g.
+/// `map/entities.map.navmesh.json`. Levels of LDtk projects get their
+/// own file.
+pub fn nav_mesh_path(map_file: &str) -> PathBuf {
+    let (map_file, level) = split_label(map_file);
+    let mut file_name = Path::new(map_file)
+        .file_stem()
+        .unwrap_or_default()
+        .to_os_string();
+    if let Some(level) = level {
+        file_name.push(".");
+        file_name.push(level);
+    }
+    file_name.push(".");
+    file_name.push(NAV_MESH_EXTENSION);
+    Path::new(map_file).with_file_name(file_name)
+}
+
+/// Walkable area of the world made of convex polygons. Paths are
+/// searched on the polygons instead of the cells of the collision map,
+/// so only a fraction of the nodes has to be visited. The polygons are
+/// shrunk by the agent radius, so every position inside of them can be
+/// reached by the agents.
+#[derive(Resource, Debug, Default)]
+pub struct NavMesh {
+    agent_radius: f32,
+    /// [`NavGrid::content_hash`] of the grid the mesh was baked from
+    grid_hash: u64,
+    /// The mesh was read from the file baked by `sauerstoff-bake`
+    loaded: bool,
+    polygons: Vec<NavPolygon>,
+}
+
+/// Rectangle of the navigation mesh
+#[derive(Debug)]
+struct NavPolygon {
+    min: Vec2,
+    max: Vec2,
+    /// Polygons sharing an edge with this one
+    neighbors: Vec<usize>,
+}
+
+impl NavPolygon {
+    fn center(&self) -> Vec2 {
+        (self.min + self.max) / 2.0
+    }
+
+    fn contains(&self, position: Vec2) -> bool {
+        position.cmpge(self.min).all() && position.cmple(self.max).all()
+    }
+}
+
+/// Navigation grid and mesh which are rebuilt in the background. The
+/// mesh is `None` if the current mesh is kept.
+#[derive(Resource, Default)]
+pub struct NavigationRebuild {
+    pub task: Option<Task<(NavGrid, Option<NavMesh>)>>,
+    /// Colliders changed while the task was running, so the grid is
+    /// built again once it is done
+    pub pending: bool,
+    /// Chunks which were loaded or unloaded since the last rebuild.
+    /// Only their cells are updated.
+    pub chunks: HashSet<ChunkCoord>,
+}
+
+/// File format of baked navigation meshes
+#[derive(Serialize, Deserialize, Debug)]
+struct NavMeshData {
+    /// [`NAV_MESH_VERSION`] the mesh was baked with
+    version: u32,
+    agent_radius: f32,
+    grid_hash: u64,
+    polygons: Vec<NavPolygonData>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct NavPolygonData {
+    min: [f32; 2],
+    max: [f32; 2],
+    neighbors: Vec<usize>,
+}
+
+/// Entry of the open list of the polygon search, ordered by the lowest
+/// estimated cost first
+#[derive(Debug, PartialEq)]
+struct OpenPolygon {
+    estimate: f32,
+    polygon: usize,
+}
+
+impl Eq for OpenPolygon {}
+
+impl Ord for OpenPolygon {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+    }
+}
+
+impl PartialOrd for OpenPolygon {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl NavMesh {
+    /// Bake the navigation mesh from the navigation grid by merging
+    /// its walkable cells into rectangles
+    pub fn bake(grid: &NavGrid, agent_radius: f32) -> Self {
+        let (width, height) = grid.size();
+        let mut owners = vec![None; width * height];
+        let is_free = |owners: &[Option<usize>], x: usize, y: usize| {
+            owners[y * width + x].is_none() && grid.is_cell_clear(x, y, agent_radius)
+        };
+        let mut polygons = Vec::new();
+        let mut cells = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                if !is_free(&owners, x, y) {
+                    continue;
+                }
+                // Grow the rectangle to the right as far as possible,
+                // then upwards as long as the whole row is free
+                let mut max_x = x;
+                while max_x + 1 < width && is_free(&owners, max_x + 1, y) {
+                    max_x += 1;
+                }
+                let mut max_y = y;
+                while max_y + 1 < height && (x..=max_x).all(|x| is_free(&owners, x, max_y + 1)) {
+                    max_y += 1;
+                }
+                for row in y..=max_y {
+                    owners[row * width + x..=row * width + max_x].fill(Some(polygons.len()));
+                }
+                polygons.push(NavPolygon {
+                    min: grid.cell_bounds(x, y).0,
+                    max: grid.cell_bounds(max_x, max_y).1,
+                    neighbors: Vec::new(),
+                });
+                cells.push(((x, y), (max_x, max_y)));
+            }
+        }
+        // Rectangles touching the right or upper edge of a rectangle are
+        // its neighbors and it is theirs
+        for (polygon, ((min_x, min_y), (max_x, max_y))) in cells.into_iter().enumerate() {
+            let right = (max_x + 1 < width)
+                .then(|| (min_y..=max_y).map(move |y| y * width + max_x + 1))
+                .into_iter()
+                .flatten();
+            let above = (max_y + 1 < height)
+                .then(|| (min_x..=max_x).map(move |x| (max_y + 1) * width + x))
+                .into_iter()
+                .flatten();
+            for neighbor in right.chain(above).filter_map(|cell| owners[cell]) {
+                if !polygons[polygon].neighbors.contains(&neighbor) {
+                    polygons[polygon].neighbors.push(neighbor);
+                    polygons[neighbor].neighbors.push(polygon);
+                }
+            }
+        }
+        Self {
+            agent_radius,
+            grid_hash: grid.content_hash(),
+            loaded: false,
+            polygons,
+        }
+    }
+
+    /// Read a navigation mesh baked by `sauerstoff-bake`. Meshes baked
+    /// with another version of the baking or for another agent radius
+    /// are rejected. Whether the collision changed since is checked by
+    /// [`NavMesh::is_baked_from`].
+    pub fn load(path: &Path, agent_radius: f32) -> Result<Self, LoadError> {
+        let data: NavMeshData = read_json(path)?;
+        if data.version != NAV_MESH_VERSION || data.agent_radius != agent_radius {
+            return Err(LoadError::OutdatedNavMesh {
+                path: path.to_owned(),
+            });
+        }
+        Ok(Self::from_data(data))
+    }
+
+    /// Check if the mesh was read from the file baked by
+    /// `sauerstoff-bake` instead of being baked by the game
+    pub fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    /// Check if the mesh was baked from a grid with the same cells
+    pub fn is_baked_from(&self, grid: &NavGrid) -> bool {
+        self.grid_hash == grid.content_hash()
+    }
+
+    fn from_data(data: NavMeshData) -> Self {
+        Self {
+            agent_radius: data.agent_radius,
+            grid_hash: data.grid_hash,
+            loaded: true,
+            polygons: data
+                .polygons
+                .into_iter()
+                .map(|polygon| NavPolygon {
+                    min: Vec2::from_array(polygon.min),
+                    max: Vec2::from_array(polygon.max),
+                    neighbors: polygon.neighbors,
+                })
+                .collect(),
+        }
+    }
+
+    fn to_data(&self) -> NavMeshData {
+        NavMeshData {
+            version: NAV_MESH_VERSION,
+            agent_radius: self.agent_radius,
+            grid_hash: self.grid_hash,
+            polygons: self
+                .polygons
+                .iter()
+                .map(|polygon| NavPolygonData {
+                    min: polygon.min.to_array(),
+                    max: polygon.max.to_array(),
+                    neighbors: polygon.neighbors.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Store the navigation mesh for [`NavMesh::load`]
+    pub fn write(&self, path: &Path) -> Result<(), LoadError> {
+        let json = serde_json::to_vec(&self.to_data())
+            .expect("navigation meshes can always be serialized");
+        std::fs::write(path, json).map_err(|e| LoadError::io(path, e))
+    }
+
+    pub fn agent_radius(&self) -> f32 {
+        self.agent_radius
+    }
+
+    fn polygon_at(&self, position: Vec2) -> Option<usize> {
+        self.polygons
+            .iter()
+            .position(|polygon| polygon.contains(position))
+    }
+
+    /// Closest walkable position and the polygon containing it
+    fn nearest(&self, position: Vec2) -> Option<(usize, Vec2)> {
+        if let Some(polygon) = self.polygon_at(position) {
+            return Some((polygon, position));
+        }
+        self.polygons
+            .iter()
+            .map(|polygon| position.clamp(polygon.min, polygon.max))
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                a.distance_squared(position)
+                    .total_cmp(&b.distance_squared(position))
+            })
+    }
+
+    /// Closest position an agent can reach, `None` if the mesh is empty
+    pub fn nearest_walkable(&self, position: Vec3) -> Option<Vec3> {
+        self.nearest(position.truncate())
+            .map(|(_, nearest)| nearest.extend(position.z))
+    }
+
+    /// Find the shortest path through the mesh. Like
+    /// [`Pathfinding::find_path`](super::pathfinding::Pathfinding::find_path)
+    /// the path contains the waypoints after the start. Start and goal
+    /// outside of the mesh are moved to the closest walkable position.
+    pub fn find_path(&self, start: Vec3, goal: Vec3) -> Option<Vec<Vec3>> {
+        let (start_polygon, start_position) = self.nearest(start.truncate())?;
+        let (goal_polygon, goal_position) = self.nearest(goal.truncate())?;
+        let polygons =
+            self.find_polygons(start_polygon, start_position, goal_polygon, goal_position)?;
+
+        let mut portals = vec![(start_position, start_position)];
+        for pair in polygons.windows(2) {
+            portals.push(self.portal(pair[0], pair[1]));
+        }
+        portals.push((goal_position, goal_position));
+        Some(
+            string_pull(&portals)
+                .into_iter()
+                .map(|point| point.extend(goal.z))
+                .collect(),
+        )
+    }
+
+    /// A* search on the polygons. The cost of a polygon is the length of
+    /// the path through the midpoints of the crossed edges.
+    fn find_polygons(
+        &self,
+        start: usize,
+        start_position: Vec2,
+        goal: usize,
+        goal_position: Vec2,
+    ) -> Option<Vec<usize>> {
+        let mut costs = vec![f32::INFINITY; self.polygons.len()];
+        let mut entries = vec![start_position; self.polygons.len()];
+        let mut came_from = vec![None; self.polygons.len()];
+        let mut open = BinaryHeap::new();
+        costs[start] = 0.0;
+        open.push(OpenPolygon {
+            estimate: start_position.distance(goal_position),
+            polygon: start,
+        });
+        while let Some(OpenPolygon { estimate, polygon }) = open.pop() {
+            if polygon == goal {
+                let mut polygons = vec![goal];
+                while let Some(previous) = came_from[*polygons.last().unwrap()] {
+                    polygons.push(previous);
+                }
+                polygons.reverse();
+                return Some(polygons);
+            }
+            // Skip outdated entries of polygons which were reached cheaper
+            if estimate > costs[polygon] + entries[polygon].distance(goal_position) {
+                continue;
+            }
+            for &neighbor in self.polygons[polygon].neighbors.iter() {
+                let (left, right) = self.portal(polygon, neighbor);
+                let entry = (left + right) / 2.0;
+                let cost = costs[polygon] + entries[polygon].distance(entry);
+                if cost < costs[neighbor] {
+                    costs[neighbor] = cost;
+                    entries[neighbor] = entry;
+                    came_from[neighbor] = Some(polygon);
+                    open.push(OpenPolygon {
+                        estimate: cost + entry.distance(goal_position),
+                        polygon: neighbor,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// Edge shared by two neighbor polygons as left and right end point
+    /// seen when walking from the first polygon to the second one
+    fn portal(&self, from: usize, to: usize) -> (Vec2, Vec2) {
+        let (from, to) = (&self.polygons[from], &self.polygons[to]);
+        let (a, b) = (from.min.max(to.min), from.max.min(to.max));
+        let direction = to.center() - from.center();
+        if direction.perp_dot(a - from.center()) > direction.perp_dot(b - from.center()) {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+}
+
+/// Shortest path through a sequence of portals given by their left
+/// and right end points, using the simple stupid funnel algorithm. The
+/// first portal is the start and the last one the goal. The start is
+/// not part of the path.
+fn string_pull(portals: &[(Vec2, Vec2)]) -> Vec<Vec2> {
+    let mut path = Vec::new();
+    let (mut apex, mut left, mut right) = (portals[0].0, portals[0].0, portals[0].1);
+    let (mut left_index, mut right_index) = (0, 0);
+    let mut i = 1;
+    while i < portals.len() {
+        let (portal_left, portal_right) = portals[i];
+        // Narrow the funnel from the right unless it crosses the left
+        // side, which then becomes the next corner of the path
+        if (right - apex).perp_dot(portal_right - apex) >= 0.0 {
+            if apex == right || (left - apex).perp_dot(portal_right - apex) < 0.0 {
+                right = portal_right;
+                right_index = i;
+            } else {
+                path.push(left);
+                apex = left;
+                right = left;
+                right_index = left_index;
+                i = left_index + 1;
+                continue;
+            }
+        }
+        if (left - apex).perp_dot(portal_left - apex) <= 0.0 {
+            if apex == left || (right - apex).perp_dot(portal_left - apex) > 0.0 {
+                left = portal_left;
+                left_index = i;
+            } else {
+                path.push(right);
+                apex = right;
+                left = right;
+                left_index = right_index;
+                i = right_index + 1;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    let goal = portals[portals.len() - 1].0;
+    if path.last() != Some(&goal) {
+        path.push(goal);
+    }
+    path
+}
+
+#[test]
+fn test_nav_mesh() {
+    use super::{
+        geometry::{Geometry, GeometryData},
+        map::Map,
+    };
+
+    // Two rooms connected by a 96 pixel wide door in the wall at x = 0
+    let data = GeometryData {
+        polygons: vec![vec![
+            [-160.0, -160.0],
+            [160.0, -160.0],
+            [160.0, 160.0],
+            [-160.0, 160.0],
+        ]],
+        polylines: vec![
+            vec![[0.0, -160.0], [0.0, -16.0]],
+            vec![[0.0, 80.0], [0.0, 160.0]],
+        ],
+    };
+    let map = Map::Geometry(Geometry::from_data(Path::new("test.yaml"), data).unwrap());
+    let grid = NavGrid::build(&map, &[]);
+    let nav_mesh = NavMesh::bake(&grid, 8.0);
+    let (start, goal) = (Vec3::new(-100.0, -50.0, 0.0), Vec3::new(100.0, -50.0, 0.0));
+
+    let path = nav_mesh.find_path(start, goal).unwrap();
+    assert_eq!(*path.last().unwrap(), goal);
+    // Only the corners of the door are needed
+    assert_eq!(path.len(), 3, "{:?}", path);
+    for (from, to) in std::iter::once(start)
+        .chain(path.iter().copied())
+        .zip(path.iter())
+    {
+        assert_eq!(
+            map.collide(from, *to),
+            super::map::Movement::Free,
+            "{} -> {}",
+            from,
+            to
+        );
+    }
+
+    // Positions in the wall are moved next to it
+    let nearest = nav_mesh
+        .nearest_walkable(Vec3::new(2.0, -40.0, 0.0))
+        .unwrap();
+    assert!(!map.is_blocked(nearest));
+    assert!(nearest.distance(Vec3::new(2.0, -40.0, 0.0)) <= 32.0);
+
+    // Stored meshes find the same paths
+    let data = serde_json::to_string(&nav_mesh.to_data()).unwrap();
+    let stored = NavMesh::from_data(serde_json::from_str(&data).unwrap());
+    assert_eq!(stored.find_path(start, goal), Some(path));
+
+    // Meshes baked from another collision are outdated
+    assert!(stored.is_baked_from(&grid));
+    let without_wall = GeometryData {
+        polygons: vec![vec![
+            [-160.0, -160.0],
+            [160.0, -160.0],
+            [160.0, 160.0],
+            [-160.0, 160.0],
+        ]],
+        polylines: Vec::new(),
+    };
+    let map = Map::Geometry(Geometry::from_data(Path::new("test.yaml"), without_wall).unwrap());
+    assert!(!stored.is_baked_from(&NavGrid::build(&map, &[])));
+
+    assert_eq!(
+        nav_mesh_path("map/entities.map.yaml"),
+        Path::new("map/entities.map.navmesh.json")
+    );
+    assert_eq!(
+        nav_mesh_path("map/world.ldtk#Level_1"),
+        Path::new("map/world.Level_1.navmesh.json")
+    );
+}
@@ -0,0 +1,506 @@
+use std::{cmp::Reverse, collections::BinaryHeap, f32::consts::SQRT_2, sync::Arc};
+
+use bevy::{
+    ecs::system::Resource,
+    math::{Vec2, Vec3},
+    tasks::{AsyncComputeTaskPool, Task},
+};
+
+use crate::components::collision::Collision;
+
+use super::map::Map;
+
+/// Size of the navigation grid cells in pixels
+const CELL_SIZE: f32 = 16.0;
+
+/// Clearance in cells at which the distance to blocked cells is no
+/// longer tracked. Agents may have a radius of up to 500 pixels. Only
+/// cells this close to a change have to be updated.
+const MAX_CLEARANCE: f32 = 32.0;
+
+/// Maximum distance in cells the start and goal are moved to reach a
+/// walkable cell, e.g. for goals next to walls
+const MAX_SNAP_DISTANCE: i32 = 8;
+
+/// Costs of moving to a neighbor cell. Diagonal steps cost about
+/// sqrt(2) times as much as straight steps.
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+/// Finds paths through the world. The navigation grid is shared with
+/// background tasks, so long queries can run off the main thread.
+#[derive(Resource, Clone, Default)]
+pub struct Pathfinding {
+    grid: Arc<NavGrid>,
+}
+
+impl Pathfinding {
+    pub fn new(grid: NavGrid) -> Self {
+        Self {
+            grid: Arc::new(grid),
+        }
+    }
+
+    /// Grid the paths are searched on
+    pub fn grid(&self) -> Arc<NavGrid> {
+        self.grid.clone()
+    }
+
+    /// Find a smoothed path for an agent with the given radius. The
+    /// path contains the waypoints after the start and ends at the
+    /// goal or the closest reachable position next to it. `None` is
+    /// returned if the goal can't be reached.
+    pub fn find_path(&self, start: Vec3, goal: Vec3, agent_radius: f32) -> Option<Vec<Vec3>> {
+        self.grid.find_path(start, goal, agent_radius)
+    }
+
+    /// Run `find_path` on the async compute task pool
+    pub fn find_path_async(
+        &self,
+        start: Vec3,
+        goal: Vec3,
+        agent_radius: f32,
+    ) -> Task<Option<Vec<Vec3>>> {
+        let grid = self.grid.clone();
+        AsyncComputeTaskPool::get().spawn(async move { grid.find_path(start, goal, agent_radius) })
+    }
+}
+
+/// Walkable cells of the world. Each cell stores its distance to the
+/// closest blocked cell, so agents of any size can use the same grid.
+#[derive(Debug, Default, Clone)]
+pub struct NavGrid {
+    /// Lower left corner of the first cell
+    origin: Vec2,
+    width: usize,
+    height: usize,
+    blocked: Vec<bool>,
+    /// Distance in cells to the closest blocked cell up to
+    /// [`MAX_CLEARANCE`], 0 for blocked cells
+    clearance: Vec<f32>,
+}
+
+impl NavGrid {
+    /// Build the grid from the world collision and the colliders of
+    /// entities which don't move
+    pub fn build(map: &Map, colliders: &[&Collision]) -> Self {
+        let (min, max) = map.bounds();
+        // The map is surrounded by a ring of blocked cells
+        let origin = min - Vec2::splat(CELL_SIZE);
+        let width = ((max.x - min.x) / CELL_SIZE).ceil() as usize + 2;
+        let height = ((max.y - min.y) / CELL_SIZE).ceil() as usize + 2;
+        let mut grid = Self {
+            origin,
+            width,
+            height,
+            blocked: vec![true; width * height],
+            clearance: vec![0.0; width * height],
+        };
+        grid.update_cells(map, colliders, (0, 0), (width - 1, height - 1));
+        grid
+    }
+
+    /// Update the cells inside of an area of the world, e.g. a chunk of
+    /// a streamed map which was loaded or unloaded. The blocked cells of
+    /// the area are replaced, so the colliders have to be passed again.
+    pub fn update_area(&mut self, map: &Map, colliders: &[&Collision], min: Vec2, max: Vec2) {
+        let (Some(first), Some(last)) = (self.clamped_cell(min), self.clamped_cell(max)) else {
+            return;
+        };
+        self.update_cells(map, colliders, first, last);
+    }
+
+    /// Update the blocked cells between the `first` and the `last` cell
+    /// and the clearance of all cells which depends on them
+    fn update_cells(
+        &mut self,
+        map: &Map,
+        colliders: &[&Collision],
+        (min_x, min_y): (usize, usize),
+        (max_x, max_y): (usize, usize),
+    ) {
+        let (width, height) = (self.width, self.height);
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let (cell_min, cell_max) = self.cell_bounds(x, y);
+                self.blocked[y * width + x] = x == 0
+                    || y == 0
+                    || x == width - 1
+                    || y == height - 1
+                    || map.is_area_blocked(cell_min, cell_max);
+            }
+        }
+        for collision in colliders {
+            let (collision_min, collision_max) = collision.world_bounds();
+            let (Some((first_x, first_y)), Some((last_x, last_y))) = (
+                self.clamped_cell(collision_min),
+                self.clamped_cell(collision_max),
+            ) else {
+                continue;
+            };
+            for y in first_y.max(min_y)..=last_y.min(max_y) {
+                for x in first_x.max(min_x)..=last_x.min(max_x) {
+                    let (cell_min, cell_max) = self.cell_bounds(x, y);
+                    if collision.overlaps_area(cell_min, cell_max) {
+                        self.blocked[y * width + x] = true;
+                    }
+                }
+            }
+        }
+
+        // The clearance only changes up to `MAX_CLEARANCE` cells around
+        // the area. All blocked cells these cells can see are inside of
+        // a window twice as large.
+        let margin = MAX_CLEARANCE as usize;
+        let expand = |margin: usize| {
+            (
+                (min_x.saturating_sub(margin), min_y.saturating_sub(margin)),
+                (
+                    (max_x + margin).min(width - 1),
+                    (max_y + margin).min(height - 1),
+                ),
+            )
+        };
+        let ((inner_min_x, inner_min_y), (inner_max_x, inner_max_y)) = expand(margin);
+        let ((window_min_x, window_min_y), (window_max_x, window_max_y)) = expand(2 * margin);
+        let window_width = window_max_x - window_min_x + 1;
+        let window_height = window_max_y - window_min_y + 1;
+        let blocked = (window_min_y..=window_max_y)
+            .flat_map(|y| &self.blocked[y * width + window_min_x..=y * width + window_max_x])
+            .copied()
+            .collect::<Vec<_>>();
+        let distances = distance_transform(&blocked, window_width, window_height);
+        for y in inner_min_y..=inner_max_y {
+            for x in inner_min_x..=inner_max_x {
+                let distance = distances[(y - window_min_y) * window_width + x - window_min_x];
+                self.clearance[y * width + x] = distance.min(MAX_CLEARANCE);
+            }
+        }
+    }
+
+    /// Hash of the cells, which doesn't change between builds or Rust
+    /// releases. Baked navigation meshes store it, so a change of the
+    /// collision they were baked from is noticed.
+    pub fn content_hash(&self) -> u64 {
+        // FNV-1a
+        let words = [
+            self.origin.x.to_bits(),
+            self.origin.y.to_bits(),
+            self.width as u32,
+            self.height as u32,
+        ];
+        let clearance = self.clearance.iter().map(|clearance| clearance.to_bits());
+        words
+            .into_iter()
+            .chain(clearance)
+            .flat_map(u32::to_le_bytes)
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+            })
+    }
+
+    /// Number of columns and rows
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Lower left and upper right corner of a cell
+    pub fn cell_bounds(&self, x: usize, y: usize) -> (Vec2, Vec2) {
+        let min = self.origin + Vec2::new(x as f32, y as f32) * CELL_SIZE;
+        (min, min + Vec2::splat(CELL_SIZE))
+    }
+
+    fn cell_center(&self, index: usize) -> Vec2 {
+        let (x, y) = (index % self.width, index / self.width);
+        self.origin + (Vec2::new(x as f32, y as f32) + 0.5) * CELL_SIZE
+    }
+
+    /// Cell containing the position, positions outside of the grid are
+    /// moved to the closest cell
+    fn clamped_cell(&self, position: Vec2) -> Option<(usize, usize)> {
+        if self.width == 0 || self.height == 0 || !position.is_finite() {
+            return None;
+        }
+        let cell = ((position - self.origin) / CELL_SIZE).floor();
+        Some((
+            (cell.x.max(0.0) as usize).min(self.width - 1),
+            (cell.y.max(0.0) as usize).min(self.height - 1),
+        ))
+    }
+
+    /// Index of the cell containing the position
+    fn cell(&self, position: Vec2) -> Option<usize> {
+        let cell = ((position - self.origin) / CELL_SIZE).floor();
+        (cell.x >= 0.0
+            && cell.y >= 0.0
+            && (cell.x as usize) < self.width
+            && (cell.y as usize) < self.height)
+            .then(|| cell.y as usize * self.width + cell.x as usize)
+    }
+
+    /// Check if an agent with the given radius fits into a cell. The
+    /// clearance is measured between the cell centers, the blocked
+    /// area starts half a cell before the center of a blocked cell.
+    fn is_walkable(&self, index: usize, agent_radius: f32) -> bool {
+        self.clearance[index] > 0.0 && (self.clearance[index] - 0.5) * CELL_SIZE >= agent_radius
+    }
+
+    /// Check if every position of a cell keeps the agent radius to the
+    /// blocked cells, not just the cell center
+    pub fn is_cell_clear(&self, x: usize, y: usize, agent_radius: f32) -> bool {
+        self.is_walkable(y * self.width + x, agent_radius + CELL_SIZE / 2.0)
+    }
+
+    /// Check if an agent can walk in a straight line between two
+    /// positions
+    fn is_line_walkable(&self, from: Vec2, to: Vec2, agent_radius: f32) -> bool {
+        let steps = (from.distance(to) / (CELL_SIZE / 4.0)).ceil().max(1.0) as usize;
+        (0..=steps).all(|step| {
+            self.cell(from.lerp(to, step as f32 / steps as f32))
+                .is_some_and(|cell| self.is_walkable(cell, agent_radius))
+        })
+    }
+
+    /// Find the walkable cell closest to the position
+    fn nearest_walkable(&self, position: Vec2, agent_radius: f32) -> Option<usize> {
+        let (x, y) = self.clamped_cell(position)?;
+        let (x, y) = (x as i32, y as i32);
+        for distance in 0..=MAX_SNAP_DISTANCE {
+            let nearest = (-distance..=distance)
+                .flat_map(|dy| (-distance..=distance).map(move |dx| (dx, dy)))
+                // Only the ring of cells at this distance
+                .filter(|(dx, dy)| dx.abs() == distance || dy.abs() == distance)
+                .map(|(dx, dy)| (x + dx, y + dy))
+                .filter(|(x, y)| {
+                    *x >= 0 && *y >= 0 && (*x as usize) < self.width && (*y as usize) < self.height
+                })
+                .map(|(x, y)| y as usize * self.width + x as usize)
+                .filter(|index| self.is_walkable(*index, agent_radius))
+                .min_by(|a, b| {
+                    self.cell_center(*a)
+                        .distance_squared(position)
+                        .total_cmp(&self.cell_center(*b).distance_squared(position))
+                });
+            if nearest.is_some() {
+                return nearest;
+            }
+        }
+        None
+    }
+
+    fn find_path(&self, start: Vec3, goal: Vec3, agent_radius: f32) -> Option<Vec<Vec3>> {
+        let (start, goal_position) = (start.truncate(), goal.truncate());
+        let start_cell = self.nearest_walkable(start, agent_radius)?;
+        let goal_cell = self.nearest_walkable(goal_position, agent_radius)?;
+        let cells = self.a_star(start_cell, goal_cell, agent_radius)?;
+
+        let mut points = vec![start];
+        points.extend(cells.into_iter().map(|cell| self.cell_center(cell)));
+        if self.is_line_walkable(*points.last().unwrap(), goal_position, agent_radius) {
+            points.push(goal_position);
+        }
+        Some(
+            self.smooth(&points, agent_radius)
+                .into_iter()
+                .map(|point| point.extend(goal.z))
+                .collect(),
+        )
+    }
+
+    /// Remove waypoints which can be skipped by walking in a straight
+    /// line. The first point is the start and not part of the result.
+    fn smooth(&self, points: &[Vec2], agent_radius: f32) -> Vec<Vec2> {
+        let mut waypoints = Vec::new();
+        let mut anchor = 0;
+        while anchor < points.len() - 1 {
+            // Farthest point which can be reached directly. The next
+            // point is used if not even that one is reachable, e.g.
+            // when the start is too close to a wall.
+            let next = (anchor + 2..points.len())
+                .take_while(|i| self.is_line_walkable(points[anchor], points[*i], agent_radius))
+                .last()
+                .unwrap_or(anchor + 1);
+            waypoints.push(points[next]);
+            anchor = next;
+        }
+        waypoints
+    }
+
+    fn a_star(&self, start: usize, goal: usize, agent_radius: f32) -> Option<Vec<usize>> {
+        let (goal_x, goal_y) = ((goal % self.width) as i32, (goal / self.width) as i32);
+        // Octile distance to the goal
+        let heuristic = |index: usize| {
+            let dx = ((index % self.width) as i32 - goal_x).unsigned_abs();
+            let dy = ((index / self.width) as i32 - goal_y).unsigned_abs();
+            STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)
+        };
+        let mut costs = vec![u32::MAX; self.clearance.len()];
+        let mut came_from = vec![usize::MAX; self.clearance.len()];
+        let mut open = BinaryHeap::new();
+        costs[start] = 0;
+        open.push(Reverse((heuristic(start), start)));
+        while let Some(Reverse((estimate, current))) = open.pop() {
+            if current == goal {
+                let mut path = vec![goal];
+                while let Some(&previous) = came_from.get(*path.last().unwrap()) {
+                    if previous == usize::MAX {
+                        break;
+                    }
+                    path.push(previous);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            // Skip outdated entries of cells which were reached cheaper
+            if estimate > costs[current] + heuristic(current) {
+                continue;
+            }
+            for (neighbor, step_cost) in self.neighbors(current, agent_radius) {
+                let cost = costs[current] + step_cost;
+                if cost < costs[neighbor] {
+                    costs[neighbor] = cost;
+                    came_from[neighbor] = current;
+                    open.push(Reverse((cost + heuristic(neighbor), neighbor)));
+                }
+            }
+        }
+        None
+    }
+
+    /// Walkable neighbors of a walkable cell. Diagonal steps are only
+    /// allowed if they don't cut a corner. Walkable cells are never
+    /// part of the blocked ring around the grid, so all neighbors
+    /// exist.
+    fn neighbors(&self, index: usize, agent_radius: f32) -> Vec<(usize, u32)> {
+        let width = self.width as isize;
+        let walkable = |offset: isize| {
+            let neighbor = (index as isize + offset) as usize;
+            self.is_walkable(neighbor, agent_radius).then_some(neighbor)
+        };
+        let mut neighbors = Vec::with_capacity(8);
+        for offset in [-1, 1, -width, width] {
+            if let Some(neighbor) = walkable(offset) {
+                neighbors.push((neighbor, STRAIGHT_COST));
+            }
+        }
+        for (dx, dy) in [(-1, -width), (1, -width), (-1, width), (1, width)] {
+            if walkable(dx).is_some() && walkable(dy).is_some() {
+                if let Some(neighbor) = walkable(dx + dy) {
+                    neighbors.push((neighbor, DIAGONAL_COST));
+                }
+            }
+        }
+        neighbors
+    }
+}
+
+/// Approximate the distance of every cell to the closest blocked cell
+/// with a two pass chamfer distance transform
+fn distance_transform(blocked: &[bool], width: usize, height: usize) -> Vec<f32> {
+    let mut distances = blocked
+        .iter()
+        .map(|blocked| if *blocked { 0.0 } else { f32::INFINITY })
+        .collect::<Vec<_>>();
+    let neighbors = [
+        (-1, 0, 1.0),
+        (0, -1, 1.0),
+        (-1, -1, SQRT_2),
+        (1, -1, SQRT_2),
+    ];
+    let mut pass = |cells: &mut dyn Iterator<Item = (usize, usize)>, sign: isize| {
+        for (x, y) in cells {
+            let index = y * width + x;
+            for (dx, dy, cost) in neighbors {
+                let (nx, ny) = (x as isize + dx * sign, y as isize + dy * sign);
+                if nx < 0 || ny < 0 || nx >= width as isize || ny >= height as isize {
+                    continue;
+                }
+                let neighbor = distances[ny as usize * width + nx as usize] + cost;
+                if neighbor < distances[index] {
+                    distances[index] = neighbor;
+                }
+            }
+        }
+    };
+    pass(
+        &mut (0..height).flat_map(|y| (0..width).map(move |x| (x, y))),
+        1,
+    );
+    pass(
+        &mut (0..height)
+            .rev()
+            .flat_map(|y| (0..width).rev().map(move |x| (x, y))),
+        -1,
+    );
+    distances
+}
+
+#[test]
+fn test_pathfinding() {
+    use super::geometry::{Geometry, GeometryData};
+    use std::path::Path;
+
+    // Two rooms connected by a 48 pixel wide door in the wall at x = 0
+    let data = GeometryData {
+        polygons: vec![vec![
+            [-160.0, -80.0],
+            [160.0, -80.0],
+            [160.0, 80.0],
+            [-160.0, 80.0],
+        ]],
+        polylines: vec![
+            vec![[0.0, -80.0], [0.0, 16.0]],
+            vec![[0.0, 64.0], [0.0, 80.0]],
+        ],
+    };
+    let map = Map::Geometry(Geometry::from_data(Path::new("test.yaml"), data).unwrap());
+    let pathfinding = Pathfinding::new(NavGrid::build(&map, &[]));
+    let (start, goal) = (Vec3::new(-100.0, -50.0, 0.0), Vec3::new(100.0, -50.0, 0.0));
+
+    let path = pathfinding.find_path(start, goal, 8.0).unwrap();
+    assert_eq!(*path.last().unwrap(), goal);
+    // Straight segments which don't cross the wall
+    assert!(path.len() <= 4, "{:?}", path);
+    for (from, to) in std::iter::once(start)
+        .chain(path.iter().copied())
+        .zip(path.iter())
+    {
+        assert_eq!(
+            map.collide(from, *to),
+            super::map::Movement::Free,
+            "{} -> {}",
+            from,
+            to
+        );
+    }
+    // The door is too small for large agents
+    assert_eq!(pathfinding.find_path(start, goal, 40.0), None);
+}
+
+#[test]
+fn test_update_area() {
+    use image::{GrayImage, Luma};
+
+    let before = Map::from_image(GrayImage::from_pixel(4096, 256, Luma([255])));
+    let mut image = GrayImage::from_pixel(4096, 256, Luma([255]));
+    for y in 64..128 {
+        for x in 64..128 {
+            image.put_pixel(x, y, Luma([0]));
+        }
+    }
+    let after = Map::from_image(image);
+    let mut grid = NavGrid::build(&before, &[]);
+    grid.update_area(
+        &after,
+        &[],
+        Vec2::new(-1984.0, 0.0),
+        Vec2::new(-1920.0, 64.0),
+    );
+    let rebuilt = NavGrid::build(&after, &[]);
+    assert_eq!(grid.blocked, rebuilt.blocked);
+    assert_eq!(grid.clearance, rebuilt.clearance);
+    assert_ne!(
+        grid.content_hash(),
+        NavGrid::build(&before, &[]).content_hash()
+    );
+}
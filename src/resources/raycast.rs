@@ -0,0 +1,147 @@
+use bevy::{
+    ecs::system::SystemParam,
+    math::Vec3,
+    prelude::{Entity, Query, Res},
+};
+
+use crate::components::collision::Collision;
+
+use super::{collision_index::CollisionIndex, map::Map};
+
+/// First obstacle hit by a ray
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    pub position: Vec3,
+    /// Entity whose collider was hit or `None` for the world collision
+    pub entity: Option<Entity>,
+}
+
+/// Cast a ray from `from` to `to` against the world collision and the
+/// given colliders. Only colliders on one of the layers in `mask` are
+/// hit, sensors never. Colliders containing `from` are ignored, so
+/// entities can cast rays from their own position.
+pub fn raycast<'a>(
+    map: &Map,
+    colliders: impl IntoIterator<Item = (Entity, &'a Collision)>,
+    from: Vec3,
+    to: Vec3,
+    mask: u32,
+) -> Option<RaycastHit> {
+    // Colliders behind the first wall can't be hit
+    let world_hit = map.raycast(from, to);
+    let end = world_hit.unwrap_or(to);
+    colliders
+        .into_iter()
+        .filter(|(_, collision)| !collision.sensor && collision.layer & mask != 0)
+        .filter_map(|(entity, collision)| {
+            collision
+                .raycast(from.truncate(), end.truncate())
+                .map(|hit| (entity, hit))
+        })
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(entity, hit)| RaycastHit {
+            position: from.lerp(end, hit),
+            entity: Some(entity),
+        })
+        .or(world_hit.map(|position| RaycastHit {
+            position,
+            entity: None,
+        }))
+}
+
+/// System parameter casting rays against the world collision and the
+/// colliders of the `CollisionIndex`
+#[derive(SystemParam)]
+pub struct Raycast<'w, 's> {
+    map: Res<'w, Map>,
+    collision_index: Res<'w, CollisionIndex>,
+    colliders: Query<'w, 's, &'static Collision>,
+}
+
+impl<'w, 's> Raycast<'w, 's> {
+    /// See [`raycast`]
+    pub fn raycast(&self, from: Vec3, to: Vec3, mask: u32) -> Option<RaycastHit> {
+        let (start, end) = (from.truncate(), to.truncate());
+        let colliders = self
+            .collision_index
+            .query(start.min(end), start.max(end))
+            .into_iter()
+            .filter_map(|entity| Some((entity, self.colliders.get(entity).ok()?)));
+        raycast(&self.map, colliders, from, to, mask)
+    }
+
+    /// Check if nothing blocks the view from one position to another.
+    /// Colliders containing `to` don't block it, so entities can be
+    /// seen by looking at their position.
+    pub fn line_of_sight(&self, from: Vec3, to: Vec3, mask: u32) -> bool {
+        match self.raycast(from, to, mask) {
+            None => true,
+            Some(RaycastHit {
+                entity: Some(entity),
+                ..
+            }) => self
+                .colliders
+                .get(entity)
+                .is_ok_and(|collision| collision.contains(to.truncate())),
+            Some(_) => false,
+        }
+    }
+}
+
+#[test]
+fn test_raycast() {
+    use crate::{components::collision::Shape, data::entity_types::CollisionLayer};
+    use bevy::math::Vec2;
+    use image::{GrayImage, Luma};
+
+    // Walkable 20x10 image with a wall at x = 5
+    let mut image = GrayImage::from_pixel(20, 10, Luma([255]));
+    for y in 0..10 {
+        image.put_pixel(15, y, Luma([0]));
+    }
+    let rock = Collision {
+        origin: Vec3::ZERO,
+        pos: Vec3::new(-2.0, 0.0, 0.0),
+        shapes: vec![Shape::Circle {
+            center: Vec2::ZERO,
+            radius: 1.0,
+        }],
+        layer: CollisionLayer::Prop.bit(),
+        mask: CollisionLayer::mask(&CollisionLayer::ALL),
+        sensor: false,
+    };
+    let entity = Entity::from_raw(0);
+    let all = CollisionLayer::mask(&CollisionLayer::ALL);
+    for map in [
+        Map::from_image(image.clone()),
+        Map::from_image(image).traced(),
+    ] {
+        let cast =
+            |from: Vec3, to: Vec3, mask: u32| raycast(&map, [(entity, &rock)], from, to, mask);
+        let (left, right) = (Vec3::new(-8.0, 0.0, 0.0), Vec3::new(8.0, 0.0, 0.0));
+
+        // The rock is in front of the wall
+        let hit = cast(left, right, all).unwrap();
+        assert_eq!(hit.entity, Some(entity));
+        assert!((hit.position.x + 3.0).abs() < 0.001);
+
+        // Other layers are ignored
+        let npc = CollisionLayer::mask(&[CollisionLayer::Npc]);
+        let hit = cast(left, right, npc).unwrap();
+        assert_eq!(hit.entity, None);
+        assert!((hit.position.x - 5.0).abs() < 0.5, "{}", hit.position);
+
+        // Rays leaving a collider don't hit it
+        assert_eq!(cast(Vec3::new(-2.0, 0.5, 0.0), left, all), None);
+
+        // Rays starting in a wall hit immediately
+        let inside = Vec3::new(5.5, 0.0, 0.0);
+        assert_eq!(
+            cast(inside, left, all),
+            Some(RaycastHit {
+                position: inside,
+                entity: None
+            })
+        );
+    }
+}
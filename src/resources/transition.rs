@@ -0,0 +1,70 @@
+use bevy::{
+    prelude::{Handle, Resource},
+    tasks::Task,
+    time::{Timer, TimerMode},
+};
+
+use crate::data::{error::LoadError, map::Map};
+
+use super::{map::Map as CollisionMap, map_registry::MapFiles};
+
+/// Duration of fading the screen out and in again
+pub const FADE_DURATION: f32 = 0.4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionPhase {
+    /// Fading to black, the map is switched afterwards
+    FadeOut,
+    /// Waiting for the new map to be loaded
+    Loading,
+    /// Fading from black to the new map, or to the previous map if the
+    /// new map could not be loaded
+    FadeIn,
+}
+
+/// Change of the current map which is in progress. The resource only
+/// exists while the player walks through a door.
+#[derive(Resource)]
+pub struct MapTransition {
+    pub map: MapFiles,
+    pub handle: Handle<Map>,
+    /// Spawn point on the new map, the player spawn is used if there is
+    /// none
+    pub spawn_point: Option<String>,
+    pub phase: TransitionPhase,
+    pub timer: Timer,
+    /// Loading of the collision map and the chunks around the spawn
+    /// points, which is started in the `Loading` phase
+    pub task: Option<Task<Result<CollisionMap, LoadError>>>,
+}
+
+impl MapTransition {
+    pub fn new(map: MapFiles, handle: Handle<Map>, spawn_point: Option<String>) -> Self {
+        Self {
+            map,
+            handle,
+            spawn_point,
+            phase: TransitionPhase::FadeOut,
+            timer: Timer::from_seconds(FADE_DURATION, TimerMode::Once),
+            task: None,
+        }
+    }
+
+    /// Opacity of the fade overlay
+    pub fn alpha(&self) -> f32 {
+        match self.phase {
+            TransitionPhase::FadeOut => self.timer.percent(),
+            TransitionPhase::Loading => 1.0,
+            TransitionPhase::FadeIn => 1.0 - self.timer.percent(),
+        }
+    }
+
+    /// Continue with the next phase and restart the fade timer
+    pub fn advance(&mut self) {
+        self.phase = match self.phase {
+            TransitionPhase::FadeOut => TransitionPhase::Loading,
+            TransitionPhase::Loading | TransitionPhase::FadeIn => TransitionPhase::FadeIn,
+        };
+        self.timer.reset();
+    }
+}
@@ -37,7 +37,7 @@ pub fn animation_system(
             timer.timer.finished()
         };
         if update {
-            let frames = &animation.frames[state.animation];
+            let frames = &animation.frames[&state.animation];
             state.index = (state.index + 1) % frames.len();
             let (atlas_index, duration) = frames[state.index];
             sprite.index = atlas_index;
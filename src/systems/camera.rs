@@ -1,14 +1,30 @@
-use bevy::prelude::{Camera, Query, Transform, Without};
+use bevy::prelude::{Camera, OrthographicProjection, Query, Res, Transform, Without};
 
-use crate::components::{followcam::FollowCam, player::Player};
+use crate::{
+    components::{followcam::FollowCam, player::Player},
+    resources::bounds::WorldBounds,
+};
 
+/// Follow the player while keeping the visible area inside of the
+/// world bounds. The visible area is taken from the projection, so the
+/// window size and scaling mode don't matter. Maps smaller than the
+/// visible area are centered.
 pub fn camera_system(
-    mut camera_query: Query<(&FollowCam, &mut Transform, Without<Player>)>,
+    mut camera_query: Query<(
+        &FollowCam,
+        &mut Transform,
+        &OrthographicProjection,
+        Without<Player>,
+    )>,
     player_query: Query<(&Player, &Transform, Without<Camera>)>,
+    world_bounds: Res<WorldBounds>,
 ) {
     let (_, player_transform, _) = player_query.single();
-    if let Ok((_, mut transform, _)) = camera_query.get_single_mut() {
-        transform.translation.x = player_transform.translation.x.clamp(-1920.0, 1920.0);
-        transform.translation.y = player_transform.translation.y.clamp(-1080.0, 1080.0);
+    if let Ok((_, mut transform, projection, _)) = camera_query.get_single_mut() {
+        let target = player_transform.translation.truncate();
+        let position = target
+            + world_bounds.push_inside(target + projection.area.min, target + projection.area.max);
+        transform.translation.x = position.x;
+        transform.translation.y = position.y;
     }
 }
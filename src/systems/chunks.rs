@@ -0,0 +1,135 @@
+use bevy::{log::warn, math::Vec2, prelude::*, tasks::AsyncComputeTaskPool, utils::HashSet};
+use futures_lite::future;
+
+use crate::{
+    asset_dir,
+    components::{
+        followcam::FollowCam,
+        map::{BackgroundChunk, MapBackground},
+    },
+    resources::{
+        chunks::{background_chunk_file, read_collision_chunk, ChunkCoord, ChunkStreaming},
+        map::Map,
+    },
+};
+
+/// Distance in pixels around the visible area in which chunks are
+/// loaded, so they are ready before they become visible
+const CHUNK_LOAD_MARGIN: f32 = 256.0;
+
+/// Distance in pixels around the visible area after which chunks are
+/// unloaded. It is larger than the load margin, so chunks at the edge
+/// aren't loaded and unloaded over and over again.
+const CHUNK_UNLOAD_MARGIN: f32 = 768.0;
+
+/// Sent when collision chunks were loaded or unloaded. Streaming only
+/// changes a part of the map, so the `Map` resource is not marked as
+/// changed.
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct ChunksChanged {
+    pub chunks: Vec<ChunkCoord>,
+}
+
+/// Load the background and collision chunks around the camera and
+/// unload the chunks far away of it. Collision chunks are read in the
+/// background and stay blocked until they are loaded.
+pub fn stream_chunks(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut map: ResMut<Map>,
+    mut streaming: ResMut<ChunkStreaming>,
+    mut chunk_events: EventWriter<ChunksChanged>,
+    camera_query: Query<(&Transform, &OrthographicProjection), With<FollowCam>>,
+    background_chunks: Query<(Entity, &BackgroundChunk)>,
+) {
+    let streaming = &mut *streaming;
+    let Map::Chunked(chunks) = map.bypass_change_detection() else {
+        streaming.tasks.clear();
+        return;
+    };
+    if streaming.dir != chunks.dir() {
+        streaming.dir = chunks.dir().to_owned();
+        streaming.tasks.clear();
+        streaming.failed.clear();
+    }
+    let Ok((transform, projection)) = camera_query.get_single() else {
+        return;
+    };
+    let center = transform.translation.truncate();
+    let (min, max) = (center + projection.area.min, center + projection.area.max);
+    let layout = chunks.layout;
+    let chunks_around = |margin: f32| {
+        layout
+            .chunks_in(min - Vec2::splat(margin), max + Vec2::splat(margin))
+            .collect::<HashSet<_>>()
+    };
+    let wanted = chunks_around(CHUNK_LOAD_MARGIN);
+    let kept = chunks_around(CHUNK_UNLOAD_MARGIN);
+
+    let mut changed = Vec::new();
+    let unloaded = chunks
+        .loaded()
+        .map(|(chunk, _)| chunk)
+        .filter(|chunk| !kept.contains(chunk))
+        .collect::<Vec<_>>();
+    for chunk in unloaded {
+        chunks.remove(chunk);
+        changed.push(chunk);
+    }
+    streaming.tasks.retain(|chunk, _| kept.contains(chunk));
+    for chunk in wanted.iter().copied() {
+        if chunks.is_loaded(chunk)
+            || streaming.tasks.contains_key(&chunk)
+            || streaming.failed.contains(&chunk)
+        {
+            continue;
+        }
+        let dir = chunks.dir().to_owned();
+        let task = AsyncComputeTaskPool::get()
+            .spawn(async move { read_collision_chunk(&dir, &layout, chunk) });
+        streaming.tasks.insert(chunk, task);
+    }
+    let failed = &mut streaming.failed;
+    streaming.tasks.retain(|chunk, task| {
+        match future::block_on(future::poll_once(task)) {
+            None => return true,
+            Some(Ok(image)) => {
+                chunks.insert(*chunk, image);
+                changed.push(*chunk);
+            }
+            Some(Err(error)) => {
+                warn!("Loading a map chunk failed: {}", error);
+                failed.insert(*chunk);
+            }
+        }
+        false
+    });
+
+    // The asset server loads relative to the asset folder
+    let dir = chunks.dir();
+    let asset_dir = asset_dir();
+    let dir = dir.strip_prefix(&asset_dir).unwrap_or(dir);
+    let mut spawned = HashSet::default();
+    for (entity, background) in background_chunks.iter() {
+        if kept.contains(&background.chunk) {
+            spawned.insert(background.chunk);
+        } else {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    for chunk in wanted.difference(&spawned).copied() {
+        commands.spawn((
+            SpriteBundle {
+                texture: asset_server.load(background_chunk_file(dir, chunk)),
+                transform: Transform::from_translation(layout.chunk_center(chunk).extend(0.0)),
+                ..Default::default()
+            },
+            MapBackground,
+            BackgroundChunk { chunk },
+        ));
+    }
+
+    if !changed.is_empty() {
+        chunk_events.send(ChunksChanged { chunks: changed });
+    }
+}
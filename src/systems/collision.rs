@@ -0,0 +1,20 @@
+use bevy::prelude::{Changed, Entity, Query, RemovedComponents, ResMut};
+
+use crate::{components::collision::Collision, resources::collision_index::CollisionIndex};
+
+/// Keep the collision index in sync with the `Collision` components.
+/// Colliders moved by `Collision::update_position` are detected as
+/// changed components.
+pub fn update_collision_index(
+    mut index: ResMut<CollisionIndex>,
+    query: Query<(Entity, &Collision), Changed<Collision>>,
+    mut removed: RemovedComponents<Collision>,
+) {
+    for entity in removed.iter() {
+        index.remove(entity);
+    }
+    for (entity, collision) in query.iter() {
+        let (min, max) = collision.world_bounds();
+        index.insert(entity, min, max);
+    }
+}
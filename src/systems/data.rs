@@ -0,0 +1,218 @@
+use std::{iter, path::PathBuf};
+
+use bevy::{
+    app::AppExit,
+    asset::{AssetServerError, HandleId, LoadState},
+    log::error,
+    prelude::{AssetServer, Assets, Commands, EventWriter, NextState, Res, ResMut},
+    utils::HashSet,
+};
+
+use crate::{
+    asset_dir,
+    components::player::PLAYER_ENTITY_TYPE,
+    data::{
+        entity_types::{entity_type_name, EntityType, EntityTypes, ENTITY_TYPES_DIR},
+        error::{LoadError, LoadErrors, LoaderErrors},
+        inheritance::TEMPLATE_EXTENSION,
+        map::{door_target, split_label, Map, MapHandle, RegistryMaps},
+        validate::{check_game_data, check_spawn_points, GameData},
+    },
+    load_collision_map, load_spawn_chunks,
+    resources::{
+        config::Config,
+        map_registry::{map_name, MapRegistry},
+    },
+    AppState,
+};
+
+/// Start loading the entity types and all maps of the registry
+pub fn load_data(
+    mut commands: Commands,
+    mut entity_types: ResMut<EntityTypes>,
+    asset_server: Res<AssetServer>,
+    config: Res<Config>,
+    registry: Res<MapRegistry>,
+    loader_errors: Res<LoaderErrors>,
+) {
+    // A missing folder is reported by `check_data` together with the
+    // other errors
+    let handles = asset_server
+        .load_folder(ENTITY_TYPES_DIR)
+        .unwrap_or_else(|e| {
+            let path = asset_dir().join(ENTITY_TYPES_DIR);
+            loader_errors.report(match e {
+                AssetServerError::AssetIoError(e) => LoadError::asset_io(&path, e),
+                _ => LoadError::MissingFile { path },
+            });
+            Vec::new()
+        });
+    for handle in handles {
+        let Some(path) = asset_server.get_handle_path(&handle) else {
+            continue;
+        };
+        let file_name = path.path().to_string_lossy();
+        if file_name.ends_with(&format!(".{}", TEMPLATE_EXTENSION)) {
+            entity_types.templates.push(handle.typed());
+        } else if let Some(name) = entity_type_name(path.path()) {
+            entity_types.handles.insert(name, handle.typed());
+        }
+    }
+    commands.insert_resource(RegistryMaps {
+        handles: registry
+            .maps
+            .iter()
+            .map(|(name, files)| (name.clone(), asset_server.load(files.map.as_str())))
+            .collect(),
+    });
+    commands.insert_resource(MapHandle {
+        handle: asset_server.load(config.map.as_str()),
+        map_file: config.map.clone(),
+    });
+}
+
+/// Check the game data once all of it is loaded. Errors are logged and
+/// stored as `LoadErrors` resource before the app exits. Otherwise the
+/// start map and its collision map are inserted as resources.
+#[allow(clippy::too_many_arguments)]
+pub fn check_data(
+    mut commands: Commands,
+    mut state: ResMut<NextState<AppState>>,
+    mut exit: EventWriter<AppExit>,
+    asset_server: Res<AssetServer>,
+    config: Res<Config>,
+    registry: Res<MapRegistry>,
+    registry_maps: Res<RegistryMaps>,
+    maps: Res<Assets<Map>>,
+    mut entity_types: ResMut<EntityTypes>,
+    entity_type_assets: Res<Assets<EntityType>>,
+    loader_errors: Res<LoaderErrors>,
+) {
+    let mut handle_ids = entity_types
+        .handles
+        .values()
+        .map(|handle| handle.id())
+        .chain(entity_types.templates.iter().map(|handle| handle.id()))
+        .chain(registry_maps.handles.values().map(|handle| handle.id()));
+    // Assets which failed are reported together with all other errors
+    let loading = handle_ids.any(|id| {
+        matches!(
+            asset_server.get_load_state(id),
+            LoadState::NotLoaded | LoadState::Loading
+        )
+    });
+    if loading {
+        return;
+    }
+
+    let asset_dir = asset_dir();
+    let path_of = |id: HandleId| {
+        asset_server
+            .get_handle_path(id)
+            .map_or_else(PathBuf::new, |path| asset_dir.join(path.path()))
+    };
+    let mut errors = LoadErrors::default();
+    errors.errors.extend(loader_errors.take());
+    let entity_types = &mut *entity_types;
+    let mut data = GameData {
+        entity_types: entity_types
+            .handles
+            .iter()
+            .filter_map(|(name, handle)| {
+                let entity_type = entity_type_assets.get(handle)?;
+                Some((name.clone(), (path_of(handle.id()), entity_type.clone())))
+            })
+            .collect(),
+        maps: Default::default(),
+    };
+    for (name, handle) in registry_maps.handles.iter() {
+        let files = &registry.maps[name];
+        let (map_file, level) = split_label(&files.map);
+        let path = asset_dir.join(map_file);
+        match (maps.get(handle), asset_server.get_load_state(handle)) {
+            (Some(map), _) => {
+                data.maps.insert(name.clone(), (path, map.clone()));
+            }
+            // Files which can't be read are not passed to the loader
+            (None, LoadState::Failed) if !path.is_file() => {
+                errors.push(LoadError::MissingFile { path });
+            }
+            // The loader adds the level of LDtk projects as labeled
+            // asset, a level which does not exist is never added
+            (None, LoadState::Loaded) => {
+                if let Some(level) = level {
+                    errors.push(LoadError::UnknownLevel {
+                        path,
+                        level: level.to_owned(),
+                    });
+                }
+            }
+            // Everything else was reported by the loader
+            _ => {}
+        }
+    }
+    check_game_data(&asset_dir, &data, &registry, &mut errors);
+
+    let start = map_name(&config.map);
+    // The player spawn is used on the start map and by doors without
+    // spawn point
+    let player_maps = data
+        .maps
+        .values()
+        .flat_map(|(_, map)| map.triggers.values())
+        .filter_map(|trigger| door_target(&trigger.trigger))
+        .filter(|door| door.spawn_point.is_none())
+        .map(|door| door.map)
+        .chain(iter::once(start))
+        .collect::<HashSet<_>>();
+    let mut start_collision_map = None;
+    for (name, (path, map)) in data.maps.iter() {
+        // The collision maps of the other maps are loaded when the
+        // player walks through a door, so make sure they can be loaded
+        // and check their spawn points as well
+        let Some(mut collision_map) = errors.collect(load_collision_map(
+            &asset_dir,
+            &registry.maps[name],
+            map,
+            &config,
+        )) else {
+            continue;
+        };
+        if errors
+            .collect(load_spawn_chunks(&mut collision_map, map))
+            .is_some()
+        {
+            let player = data
+                .entity_types
+                .get(PLAYER_ENTITY_TYPE)
+                .map(|(path, _)| path.as_path())
+                .filter(|_| player_maps.contains(name.as_str()));
+            check_spawn_points(path, map, player, &collision_map, &mut errors);
+        }
+        if name == start {
+            start_collision_map = Some((map.clone(), collision_map));
+        }
+    }
+
+    if start_collision_map.is_none() && errors.is_empty() {
+        // The start map has a format without loader
+        let path = asset_dir.join(split_label(&config.map).0);
+        errors.push(LoadError::syntax(&path, "the map can't be loaded"));
+    }
+    let Some((map, collision_map)) = start_collision_map.filter(|_| errors.is_empty()) else {
+        for error in errors.errors.iter() {
+            error!("{}", error);
+        }
+        commands.insert_resource(errors);
+        exit.send(AppExit);
+        return;
+    };
+    commands.insert_resource(map);
+    commands.insert_resource(collision_map);
+    entity_types.map = data
+        .entity_types
+        .into_iter()
+        .map(|(name, (_, entity_type))| (name, entity_type))
+        .collect();
+    state.set(AppState::Setup);
+}
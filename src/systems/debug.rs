@@ -0,0 +1,128 @@
+use bevy::{
+    math::{Vec2, Vec3},
+    prelude::{
+        Color, Gizmos, Input, KeyCode, OrthographicProjection, Query, Res, ResMut, Transform, With,
+    },
+};
+
+use crate::{
+    components::{
+        collision::{Collision, Shape},
+        followcam::FollowCam,
+        interaction::Interaction,
+        player::Player,
+    },
+    resources::{
+        debug::{DebugOverlay, DEBUG_OVERLAY_KEY},
+        map::Map,
+    },
+};
+
+/// Size of the cells used to sample blocked pixels of collision images
+const BLOCKED_CELL_SIZE: f32 = 8.0;
+
+const BLOCKED_COLOR: Color = Color::ORANGE;
+const COLLISION_COLOR: Color = Color::RED;
+const SENSOR_COLOR: Color = Color::YELLOW;
+const INTERACTION_COLOR: Color = Color::CYAN;
+const PLAYER_CENTER_COLOR: Color = Color::GREEN;
+
+pub fn toggle_debug_overlay(key: Res<Input<KeyCode>>, mut overlay: ResMut<DebugOverlay>) {
+    if key.just_pressed(DEBUG_OVERLAY_KEY) {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+pub fn draw_debug_overlay(
+    overlay: Res<DebugOverlay>,
+    mut gizmos: Gizmos,
+    map: Res<Map>,
+    camera_query: Query<(&Transform, &OrthographicProjection), With<FollowCam>>,
+    player_query: Query<(&Player, &Transform)>,
+    collision_query: Query<&Collision>,
+    interaction_query: Query<&Interaction>,
+) {
+    if !overlay.enabled {
+        return;
+    }
+    if let Ok((transform, projection)) = camera_query.get_single() {
+        let camera = transform.translation.truncate();
+        draw_blocked(
+            &mut gizmos,
+            &map,
+            camera + projection.area.min,
+            camera + projection.area.max,
+        );
+    }
+    for collision in collision_query.iter() {
+        let color = if collision.sensor {
+            SENSOR_COLOR
+        } else {
+            COLLISION_COLOR
+        };
+        let translation = (collision.pos - collision.origin).truncate();
+        for shape in collision.shapes.iter() {
+            match shape {
+                Shape::Polygon(points) => gizmos.linestrip_2d(
+                    points
+                        .iter()
+                        .chain(points.first())
+                        .map(|point| *point + translation),
+                    color,
+                ),
+                Shape::Circle { center, radius } => {
+                    gizmos.circle_2d(*center + translation, *radius, color);
+                }
+            }
+        }
+    }
+    for interaction in interaction_query.iter() {
+        gizmos.circle_2d(
+            interaction.center.truncate(),
+            interaction.max_distance.into(),
+            INTERACTION_COLOR,
+        );
+    }
+    for (player, transform) in player_query.iter() {
+        gizmos.circle_2d(
+            (transform.translation + player.center).truncate(),
+            5.0,
+            PLAYER_CENTER_COLOR,
+        );
+    }
+}
+
+/// Draw the blocked terrain inside of the given area. Collision images
+/// are sampled in cells and blocked cells of a row are merged to one
+/// rectangle.
+fn draw_blocked(gizmos: &mut Gizmos, map: &Map, min: Vec2, max: Vec2) {
+    if let Map::Geometry(geometry) = map {
+        for (points, closed) in geometry.outlines() {
+            let first = closed.then(|| points[0]);
+            gizmos.linestrip_2d(points.iter().copied().chain(first), BLOCKED_COLOR);
+        }
+        return;
+    }
+    let to_cell = |value: f32| (value / BLOCKED_CELL_SIZE).floor() as i32;
+    let (min_x, max_x) = (to_cell(min.x), to_cell(max.x));
+    for y in to_cell(min.y)..=to_cell(max.y) {
+        let cell_y = y as f32 * BLOCKED_CELL_SIZE;
+        let mut run_start = None;
+        // One cell past the area ends the last run
+        for x in min_x..=max_x + 1 {
+            let cell_x = x as f32 * BLOCKED_CELL_SIZE;
+            let center = Vec3::new(cell_x, cell_y, 0.0) + Vec3::splat(BLOCKED_CELL_SIZE / 2.0);
+            let blocked = x <= max_x && map.is_blocked(center);
+            match (blocked, run_start) {
+                (true, None) => run_start = Some(cell_x),
+                (false, Some(start)) => {
+                    let size = Vec2::new(cell_x - start, BLOCKED_CELL_SIZE);
+                    let position = Vec2::new(start, cell_y) + size / 2.0;
+                    gizmos.rect_2d(position, 0.0, size, BLOCKED_COLOR);
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+}
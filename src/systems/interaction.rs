@@ -3,19 +3,30 @@ use bevy::{
     text::Text,
 };
 
-use crate::components::{interaction::Interaction, player::Player};
+use crate::{
+    components::{interaction::Interaction, player::Player},
+    data::entity_types::CollisionLayer,
+    resources::raycast::Raycast,
+};
+
+/// Layers of the colliders which hide interactions behind them. Walls of
+/// the world collision always do.
+const INTERACTION_SIGHT_MASK: [CollisionLayer; 1] = [CollisionLayer::Prop];
 
 pub fn detect_interaction(
     player_query: Query<(&Player, &Transform)>,
     interaction_query: Query<&Interaction>,
     mut text_query: Query<&mut Text>,
+    raycast: Raycast,
 ) {
     let (player, player_transform) = player_query.single();
+    let player_center = player_transform.translation + player.center;
+    let sight_mask = CollisionLayer::mask(&INTERACTION_SIGHT_MASK);
     let interactions = interaction_query
         .iter()
         .filter(|interaction| {
-            ((player_transform.translation + player.center).distance(interaction.center))
-                <= f32::from(interaction.max_distance)
+            player_center.distance(interaction.center) <= f32::from(interaction.max_distance)
+                && raycast.line_of_sight(player_center, interaction.center, sight_mask)
         })
         .collect::<Vec<_>>();
     let text = if interactions.is_empty() {
@@ -1,25 +1,147 @@
+use std::path::Path;
+
 use bevy::{
+    log::error,
     math::Vec3,
-    prelude::{Commands, Res},
+    prelude::{
+        AssetServer, Assets, Commands, DespawnRecursiveExt, DetectChangesMut, Entity, EventWriter,
+        Handle, Image, Query, Res, ResMut, SpriteBundle, With, Without,
+    },
 };
 
 use crate::{
-    data::{entity_types::EntityTypes, map::Map},
+    asset_dir,
+    components::{
+        collision::Collision,
+        map::{BackgroundChunk, MapBackground, MapEntity},
+        trigger::Trigger,
+    },
+    data::{
+        common::{Position, Rect},
+        entity_types::{CollisionLayer, CollisionShape, CollisionShapes, EntityTypes},
+        map::{split_label, Map},
+    },
+    resources::{bounds::WorldBounds, map::Map as CollisionMap},
     spawn_entity,
+    systems::trigger::{TriggerEvent, TriggerEventKind},
 };
 
+/// Layers detected by trigger zones
+const TRIGGER_MASK: [CollisionLayer; 2] = [CollisionLayer::Player, CollisionLayer::Npc];
+
 pub fn initialize_map(mut commands: Commands, map: Res<Map>, entity_types: Res<EntityTypes>) {
+    spawn_map_entities(&mut commands, &map, &entity_types);
+}
+
+pub fn spawn_map_entities(commands: &mut Commands, map: &Map, entity_types: &EntityTypes) {
     for (name, entity) in map.entities.iter() {
-        let entity_type = entity_types
-            .map
-            .get(&entity.entity_type)
-            .unwrap_or_else(|| {
-                panic!(
-                    "Entity {:?} references non existant entity type: {}",
-                    name, entity.entity_type
-                )
-            });
+        // Unknown entity types are reported by the checks and when the
+        // map is reloaded
+        let Some(entity_type) = entity_types.map.get(&entity.entity_type) else {
+            error!(
+                "Entity {:?} references non existant entity type: {}",
+                name, entity.entity_type
+            );
+            continue;
+        };
         let position = Vec3::new(entity.position.x.into(), entity.position.y.into(), 1.0);
-        spawn_entity(&mut commands, entity_type, position, None, |_| {});
+        spawn_entity(
+            commands,
+            &entity.entity_type,
+            entity_type,
+            position,
+            None,
+            &entity.overrides,
+            |cmd| {
+                cmd.insert(MapEntity {
+                    name: name.clone(),
+                    overrides: entity.overrides.clone(),
+                });
+            },
+        );
+    }
+    for trigger in map.triggers.values() {
+        let shapes = CollisionShapes(vec![CollisionShape::Box(Rect {
+            position: Position { x: 0, y: 0 },
+            size: trigger.size,
+        })]);
+        let mut collision = Collision::from_data(trigger.size, &shapes);
+        collision.mask = CollisionLayer::mask(&TRIGGER_MASK);
+        collision.sensor = true;
+        collision.update_position(Vec3::new(
+            trigger.position.x.into(),
+            trigger.position.y.into(),
+            0.0,
+        ));
+        commands.spawn((Trigger::new(trigger.trigger.clone()), collision));
+    }
+}
+
+/// Spawn the background sprite of a map which is not streamed in
+/// chunks. Nothing is spawned if the map has no background image.
+pub fn spawn_map_background(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    map: &Map,
+    map_file: &str,
+) {
+    let background = map.background_path(Path::new(split_label(map_file).0));
+    if !asset_dir().join(&background).is_file() {
+        return;
+    }
+    commands.spawn((
+        SpriteBundle {
+            texture: asset_server.load(background),
+            ..Default::default()
+        },
+        MapBackground,
+    ));
+}
+
+/// Despawn the entities and trigger zones of the current map. Exit
+/// events are sent for all entities inside of the zones, so systems
+/// tracking the zones don't keep zones which no longer exist.
+pub fn despawn_map_entities(
+    commands: &mut Commands,
+    map_entities: &Query<Entity, With<MapEntity>>,
+    triggers: &Query<(Entity, &Trigger)>,
+    trigger_events: &mut EventWriter<TriggerEvent>,
+) {
+    for entity in map_entities.iter() {
+        commands.entity(entity).despawn_recursive();
     }
+    for (entity, trigger) in triggers.iter() {
+        for inside in trigger.inside.iter() {
+            trigger_events.send(TriggerEvent {
+                trigger: trigger.name.clone(),
+                entity: *inside,
+                kind: TriggerEventKind::Exit,
+            });
+        }
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Derive the world bounds from the map data. Explicit bounds take
+/// precedence over the size of chunked maps and the size of the
+/// background image, which is only known once it is loaded.
+pub fn update_world_bounds(
+    map: Res<Map>,
+    collision_map: Res<CollisionMap>,
+    images: Res<Assets<Image>>,
+    backgrounds: Query<&Handle<Image>, (With<MapBackground>, Without<BackgroundChunk>)>,
+    mut world_bounds: ResMut<WorldBounds>,
+) {
+    let bounds = if let Some(bounds) = map.bounds {
+        Some(WorldBounds::from(bounds))
+    } else if let CollisionMap::Chunked(_) = *collision_map {
+        let (min, max) = collision_map.bounds();
+        Some(WorldBounds { min, max })
+    } else {
+        backgrounds
+            .iter()
+            .find_map(|handle| images.get(handle))
+            .map(|image| WorldBounds::centered(image.size()))
+    };
+    world_bounds.set_if_neq(bounds.unwrap_or_default());
 }
@@ -1,9 +1,17 @@
 pub mod animation;
 pub mod camera;
+pub mod chunks;
+pub mod collision;
+pub mod data;
+pub mod debug;
 pub mod input;
 pub mod interaction;
 pub mod item;
 pub mod map;
 pub mod music;
+pub mod navigation;
 pub mod player;
+pub mod reload;
 pub mod textures;
+pub mod transition;
+pub mod trigger;
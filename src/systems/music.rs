@@ -1,9 +1,14 @@
-use bevy::prelude::{AssetServer, Assets, Commands, Query, Res, ResMut, Transform};
+use std::time::Duration;
+
+use bevy::prelude::{
+    AssetServer, Assets, Commands, Entity, EventReader, Local, Query, Res, ResMut,
+};
 use bevy_kira_audio::{Audio, AudioControl, AudioInstance, AudioTween};
 
 use crate::{
     components::player::{Player, PlayerState},
     resources::{audio::AudioInstances, config::Config},
+    systems::trigger::{TriggerEvent, TriggerEventKind},
 };
 
 pub fn music_system(
@@ -46,29 +51,59 @@ pub fn music_system(
     commands.insert_resource(music);
 }
 
+/// Prefix of trigger zones selecting the music
+pub const MUSIC_TRIGGER_PREFIX: &str = "music:";
+
+/// Music playing outside of all music zones
+const DEFAULT_MUSIC: &str = "base";
+
+/// Duration of the cross fade between two music tracks
+const MUSIC_FADE: Duration = Duration::from_secs(1);
+
 pub fn music_scene(
     config: Res<Config>,
-    query: Query<(&Player, &Transform)>,
+    query: Query<(Entity, &Player)>,
     handle: Res<AudioInstances>,
     mut audio_instances: ResMut<Assets<AudioInstance>>,
+    mut trigger_events: EventReader<TriggerEvent>,
+    // Music zones the player is inside of, the last one is playing
+    mut music_zones: Local<Vec<String>>,
+    mut playing: Local<Option<String>>,
 ) {
-    // cross fade between the two tracks depending on the current player
-    // x translation
-    let (player, transform) = query.single();
-    let mix = ((300.0 - transform.translation.x) / 600.0).clamp(0.0, 1.0);
-
-    if let Some(instance) = audio_instances.get_mut(&handle.music1) {
-        instance.set_volume(
-            (mix * config.audio.music_volume) as f64,
-            AudioTween::default(),
-        );
+    let (player_entity, player) = query.single();
+    for event in trigger_events.iter() {
+        let Some(music) = event.trigger.strip_prefix(MUSIC_TRIGGER_PREFIX) else {
+            continue;
+        };
+        if event.entity != player_entity {
+            continue;
+        }
+        match event.kind {
+            TriggerEventKind::Enter => music_zones.push(music.to_owned()),
+            TriggerEventKind::Exit => music_zones.retain(|zone| zone != music),
+        }
     }
 
-    if let Some(instance) = audio_instances.get_mut(&handle.music2) {
-        instance.set_volume(
-            ((1.0 - mix) * config.audio.music_volume) as f64,
-            AudioTween::default(),
-        );
+    // cross fade to the music of the current zone
+    let music = music_zones.last().map_or(DEFAULT_MUSIC, String::as_str);
+    if playing.as_deref() != Some(music) {
+        // The audio instances may not be available in the first frames
+        let mut faded = true;
+        for (track, instance) in handle.music() {
+            let Some(instance) = audio_instances.get_mut(instance) else {
+                faded = false;
+                continue;
+            };
+            let volume = if track == music {
+                config.audio.music_volume
+            } else {
+                0.0
+            };
+            instance.set_volume(volume as f64, AudioTween::linear(MUSIC_FADE));
+        }
+        if faded {
+            *playing = Some(music.to_owned());
+        }
     }
 
     if let Some(instance) = audio_instances.get_mut(&handle.sigh) {
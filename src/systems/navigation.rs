@@ -0,0 +1,134 @@
+use bevy::{
+    log::warn,
+    prelude::{
+        Changed, Commands, DetectChanges, Entity, EventReader, Query, RemovedComponents, Res,
+        ResMut, With,
+    },
+    tasks::AsyncComputeTaskPool,
+};
+use futures_lite::future;
+
+use crate::{
+    asset_dir,
+    components::{
+        collision::Collision,
+        map::MapEntity,
+        navigation::{PathQuery, PathResult},
+    },
+    data::{error::LoadError, map::MapHandle},
+    resources::{
+        map::Map,
+        navmesh::{nav_mesh_path, NavMesh, NavigationRebuild, NAV_MESH_AGENT_RADIUS},
+        pathfinding::{NavGrid, Pathfinding},
+    },
+    systems::chunks::ChunksChanged,
+};
+
+/// Rebuild the navigation grid and the navigation mesh in the
+/// background when the world collision or the colliders of the map
+/// entities change. Loading and unloading chunks of streamed maps only
+/// updates the cells of these chunks. Path queries keep using the
+/// previous grid until the rebuild is done. Only a new map cancels a
+/// running rebuild, other changes wait for it to finish. When the map
+/// changes, the navigation mesh baked by `sauerstoff-bake` is used if
+/// it is up to date, so only the grid has to be built. It is kept when
+/// colliders change later on, e.g. when entity types are reloaded, so
+/// the mesh only covers the colliders the map starts with. Streamed
+/// maps are never baked.
+#[allow(clippy::too_many_arguments)]
+pub fn update_navigation(
+    map_handle: Res<MapHandle>,
+    map: Res<Map>,
+    mut pathfinding: ResMut<Pathfinding>,
+    mut nav_mesh: ResMut<NavMesh>,
+    mut rebuild: ResMut<NavigationRebuild>,
+    query: Query<&Collision, With<MapEntity>>,
+    changed_query: Query<(), (With<MapEntity>, Changed<Collision>)>,
+    mut removed: RemovedComponents<Collision>,
+    mut chunk_events: EventReader<ChunksChanged>,
+) {
+    if let Some(task) = &mut rebuild.task {
+        if let Some((grid, baked)) = future::block_on(future::poll_once(task)) {
+            *pathfinding = Pathfinding::new(grid);
+            if let Some(baked) = baked {
+                *nav_mesh = baked;
+            }
+            rebuild.task = None;
+        }
+    }
+
+    let removed = removed.iter().count() > 0;
+    rebuild.pending |= !changed_query.is_empty() || removed;
+    for event in chunk_events.iter() {
+        rebuild.chunks.extend(event.chunks.iter().copied());
+    }
+    if !map.is_changed()
+        && (rebuild.task.is_some() || !rebuild.pending && rebuild.chunks.is_empty())
+    {
+        return;
+    }
+    // Building the whole grid covers the changed chunks as well
+    let chunks = std::mem::take(&mut rebuild.chunks);
+    let base_grid = (!map.is_changed() && !rebuild.pending).then(|| pathfinding.grid());
+    rebuild.pending = false;
+    let areas = match &*map {
+        Map::Chunked(chunked) => chunks
+            .into_iter()
+            .map(|chunk| chunked.layout.chunk_bounds(chunk))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let keep_nav_mesh = !map.is_changed() && nav_mesh.is_loaded();
+    let path = asset_dir().join(nav_mesh_path(&map_handle.map_file));
+    let mut baked = None;
+    if map.is_changed() && !matches!(*map, Map::Chunked(_)) {
+        match NavMesh::load(&path, NAV_MESH_AGENT_RADIUS) {
+            Ok(nav_mesh) => baked = Some(nav_mesh),
+            Err(LoadError::MissingFile { .. }) => {}
+            Err(error) => warn!("Loading the navigation mesh failed: {}", error),
+        }
+    }
+    let map = map.clone();
+    let colliders = query
+        .iter()
+        .filter(|collision| !collision.sensor)
+        .cloned()
+        .collect::<Vec<_>>();
+    rebuild.task = Some(AsyncComputeTaskPool::get().spawn(async move {
+        let colliders = colliders.iter().collect::<Vec<_>>();
+        let grid = match base_grid {
+            Some(base_grid) => {
+                let mut grid = NavGrid::clone(&base_grid);
+                for (min, max) in areas {
+                    grid.update_area(&map, &colliders, min, max);
+                }
+                grid
+            }
+            None => NavGrid::build(&map, &colliders),
+        };
+        // The baked mesh is only used if the collision didn't change
+        // since it was baked
+        let nav_mesh = match baked {
+            Some(nav_mesh) if nav_mesh.is_baked_from(&grid) => Some(nav_mesh),
+            Some(_) => {
+                warn!("{}", LoadError::OutdatedNavMesh { path });
+                Some(NavMesh::bake(&grid, NAV_MESH_AGENT_RADIUS))
+            }
+            None if keep_nav_mesh => None,
+            None => Some(NavMesh::bake(&grid, NAV_MESH_AGENT_RADIUS)),
+        };
+        (grid, nav_mesh)
+    }));
+}
+
+pub fn poll_path_queries(mut commands: Commands, mut query: Query<(Entity, &mut PathQuery)>) {
+    for (entity, mut path_query) in query.iter_mut() {
+        if let Some(waypoints) = future::block_on(future::poll_once(&mut path_query.task)) {
+            commands
+                .entity(entity)
+                .insert(PathResult { waypoints })
+                .remove::<PathQuery>();
+        }
+    }
+}
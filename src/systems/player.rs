@@ -2,6 +2,7 @@ use bevy::{
     math::vec3,
     prelude::{Query, Res, Transform, Without},
     time::Time,
+    utils::HashSet,
 };
 
 use crate::{
@@ -10,7 +11,11 @@ use crate::{
         collision::Collision,
         player::{InteractDirection, Player, PlayerDirection, PlayerState},
     },
-    resources::map::Map,
+    resources::{
+        bounds::WorldBounds,
+        collision_index::CollisionIndex,
+        map::{Map, Movement},
+    },
 };
 
 pub const PLAYER_SPEED: f32 = 600.0;
@@ -24,7 +29,9 @@ pub fn player_system(
         &mut Collision,
     )>,
     collision_query: Query<(&Collision, Without<Player>)>,
+    collision_index: Res<CollisionIndex>,
     map_collision: Res<Map>,
+    world_bounds: Res<WorldBounds>,
 ) {
     let (mut player, mut transform, mut animation, mut player_collision) = query.single_mut();
     let delta = time.delta().as_secs_f32();
@@ -62,24 +69,59 @@ pub fn player_system(
         }
     }
 
+    player.terrain = map_collision.terrain_at(transform.translation);
+
     if player.state == PlayerState::Walk {
-        // move player to new position
+        // move player to new position, the terrain slows the player down
+        let speed = PLAYER_SPEED * player.terrain.speed_factor();
         let mut new_translation = vec3(
-            transform.translation.x + player.input.x * PLAYER_SPEED * delta,
-            transform.translation.y + player.input.y * PLAYER_SPEED * delta,
+            transform.translation.x + player.input.x * speed * delta,
+            transform.translation.y + player.input.y * speed * delta,
             0.0,
         );
 
-        // now make sure we're not colliding with anything
-        for (entity_collision, _) in collision_query.iter() {
-            if let Some(trans) = player_collision.collide(new_translation, entity_collision) {
-                new_translation = trans;
+        // now make sure we're not colliding with anything nearby. The
+        // colliders are looked up along the whole movement and again
+        // around each position the player is pushed to.
+        let (start_min, start_max) = player_collision.bounds_at(transform.translation);
+        let (end_min, end_max) = player_collision.bounds_at(new_translation);
+        let (mut min, mut max) = (start_min.min(end_min), start_max.max(end_max));
+        let mut checked = HashSet::new();
+        loop {
+            let candidates = collision_index
+                .query(min, max)
+                .into_iter()
+                .filter(|entity| checked.insert(*entity))
+                .collect::<Vec<_>>();
+            if candidates.is_empty() {
+                break;
+            }
+            for entity in candidates {
+                let Ok((entity_collision, _)) = collision_query.get(entity) else {
+                    continue;
+                };
+                if let Some(trans) = player_collision.collide(new_translation, entity_collision) {
+                    new_translation = trans;
+                }
             }
+            (min, max) = player_collision.bounds_at(new_translation);
         }
 
+        // keep the player inside of the map
+        let (min, max) = player_collision.bounds_at(new_translation);
+        new_translation += world_bounds.push_inside(min, max).extend(0.0);
+
         // check collision with the world
-        if let Some(trans) = map_collision.collide(transform.translation, new_translation) {
-            new_translation = trans;
+        match map_collision.collide(transform.translation, new_translation) {
+            Movement::Free => {}
+            Movement::Clamped(trans) => new_translation = trans,
+            // push the player out of blocking terrain, e.g. after the
+            // collision map was changed below them
+            Movement::StartedInside => {
+                if let Some(trans) = map_collision.nearest_free(transform.translation) {
+                    new_translation = trans;
+                }
+            }
         }
 
         // update player position
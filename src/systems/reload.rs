@@ -0,0 +1,221 @@
+use std::path::PathBuf;
+
+use bevy::{
+    asset::LoadState,
+    log::{error, warn},
+    prelude::{
+        AssetEvent, AssetServer, Assets, Commands, Entity, EventReader, EventWriter, Handle, Image,
+        Query, Res, ResMut, Resource, Transform, With,
+    },
+    sprite::TextureAtlas,
+    utils::HashMap,
+};
+
+use crate::{
+    asset_dir, base_translation,
+    components::{
+        animation::AnimationState, entity_type::EntityTypeName, map::MapEntity, trigger::Trigger,
+    },
+    data::{
+        entity_types::{EntityType, EntityTypes},
+        error::{LoadErrors, LoaderErrors},
+        inheritance::EntityTypeTemplate,
+        map::{split_label, EntityOverrides, Map, MapHandle},
+        validate::{check_entity_type, check_map_entity},
+    },
+    insert_entity_type,
+    systems::{
+        map::{despawn_map_entities, spawn_map_entities},
+        textures::{finish_entity_type_textures, load_entity_type_textures},
+        trigger::TriggerEvent,
+    },
+};
+
+/// Entity types which were modified on disk and are waiting for their
+/// images to be loaded before the entities can be updated. The previous
+/// version of the entity type stays in use until then.
+#[derive(Resource, Default)]
+pub struct PendingReloads {
+    pub entity_types: HashMap<String, (EntityType, Vec<Handle<Image>>)>,
+}
+
+/// Entities which are updated when their entity type is reloaded
+type ReloadedEntities<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static EntityTypeName,
+        &'static Transform,
+        Option<&'static AnimationState>,
+        Option<&'static MapEntity>,
+    ),
+>;
+
+/// Check modified entity types and start loading their images. Entity
+/// types with errors are reported and the previous version is kept.
+/// Entity types extending a modified template or entity type are
+/// reloaded as well.
+#[allow(clippy::too_many_arguments)]
+pub fn reload_entity_types(
+    mut events: EventReader<AssetEvent<EntityType>>,
+    mut template_events: EventReader<AssetEvent<EntityTypeTemplate>>,
+    assets: Res<Assets<EntityType>>,
+    asset_server: Res<AssetServer>,
+    entity_types: Res<EntityTypes>,
+    map: Res<Map>,
+    map_handle: Res<MapHandle>,
+    loader_errors: Res<LoaderErrors>,
+    mut pending: ResMut<PendingReloads>,
+) {
+    // Files which fail to load are logged by the `AssetServer` and the
+    // previous version of the asset stays in use
+    loader_errors.take();
+    let asset_dir = asset_dir();
+    let asset_dir = asset_dir.as_path();
+    let mut modified = template_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => asset_server.get_handle_path(handle),
+            _ => None,
+        })
+        .map(|path| path.path().to_owned())
+        .collect::<Vec<_>>();
+    for event in events.iter() {
+        let AssetEvent::Modified { handle } = event else {
+            continue;
+        };
+        if let Some(path) = asset_server.get_handle_path(handle) {
+            modified.push(path.path().to_owned());
+        }
+        let Some(name) = entity_types
+            .handles
+            .iter()
+            .find(|(_, h)| *h == handle)
+            .map(|(name, _)| name.clone())
+        else {
+            continue;
+        };
+        let Some(asset) = assets.get(handle) else {
+            continue;
+        };
+        let mut entity_type = asset.clone();
+        let path = asset_server
+            .get_handle_path(handle)
+            .map_or_else(PathBuf::new, |path| asset_dir.join(path.path()));
+        let mut errors = LoadErrors::default();
+        check_entity_type(asset_dir, &name, &path, &entity_type, &mut errors);
+        let map_path = asset_dir.join(split_label(&map_handle.map_file).0);
+        for entity in map.entities.values() {
+            if entity.entity_type == name {
+                check_map_entity(&map_path, entity, &entity_type, &mut errors);
+            }
+        }
+        if !errors.is_empty() {
+            for error in errors.errors.iter() {
+                error!("{}", error);
+            }
+            warn!("Keeping the previous version of entity type {:?}", name);
+            continue;
+        }
+        let image_handles = load_entity_type_textures(&mut entity_type, &asset_server);
+        pending
+            .entity_types
+            .insert(name, (entity_type, image_handles));
+    }
+    // The children are checked when their reload finished
+    for handle in entity_types.handles.values() {
+        let extends_modified = assets.get(handle).is_some_and(|entity_type| {
+            entity_type
+                .parents
+                .iter()
+                .any(|parent| modified.contains(parent))
+        });
+        if let Some(path) = asset_server
+            .get_handle_path(handle)
+            .filter(|_| extends_modified)
+        {
+            asset_server.reload_asset(path.path());
+        }
+    }
+}
+
+/// Replace the entity types whose images are loaded and update their
+/// entities
+pub fn apply_entity_type_reloads(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut entity_types: ResMut<EntityTypes>,
+    mut pending: ResMut<PendingReloads>,
+    mut textures: ResMut<Assets<Image>>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    query: ReloadedEntities,
+) {
+    let loaded = pending
+        .entity_types
+        .iter()
+        .filter(|(_, (_, handles))| {
+            asset_server.get_group_load_state(handles.iter().map(|handle| handle.id()))
+                == LoadState::Loaded
+        })
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    for name in loaded {
+        let Some((mut entity_type, _)) = pending.entity_types.remove(&name) else {
+            continue;
+        };
+        finish_entity_type_textures(
+            &mut entity_type,
+            &asset_server,
+            &mut textures,
+            &mut texture_atlases,
+        );
+        let animation_names = entity_type.image.animation_names();
+        for (entity, entity_type_name, transform, animation_state, map_entity) in query.iter() {
+            if entity_type_name.name != name {
+                continue;
+            }
+            // Animations which were removed from the entity type are
+            // replaced by the initial animation
+            let animation = animation_state
+                .map(|state| state.animation.as_str())
+                .filter(|animation| animation_names.contains(animation));
+            let default_overrides = EntityOverrides::default();
+            let overrides =
+                map_entity.map_or(&default_overrides, |map_entity| &map_entity.overrides);
+            insert_entity_type(
+                &mut commands.entity(entity),
+                &entity_type,
+                base_translation(transform, overrides),
+                animation,
+                overrides,
+            );
+        }
+        entity_types.map.insert(name, entity_type);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn reload_map(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<Map>>,
+    maps: Res<Assets<Map>>,
+    map_handle: Res<MapHandle>,
+    entity_types: Res<EntityTypes>,
+    map_entities: Query<Entity, With<MapEntity>>,
+    triggers: Query<(Entity, &Trigger)>,
+    mut trigger_events: EventWriter<TriggerEvent>,
+) {
+    let modified = events.iter().any(
+        |event| matches!(event, AssetEvent::Modified { handle } if *handle == map_handle.handle),
+    );
+    if !modified {
+        return;
+    }
+    let Some(map) = maps.get(&map_handle.handle) else {
+        return;
+    };
+    despawn_map_entities(&mut commands, &map_entities, &triggers, &mut trigger_events);
+    spawn_map_entities(&mut commands, map, &entity_types);
+    commands.insert_resource(map.clone());
+}
@@ -1,14 +1,19 @@
 use std::time::Duration;
 
 use bevy::{
-    asset::LoadState,
+    asset::{AssetPath, LoadState},
+    math::{Rect, Vec2},
     prelude::{AssetServer, Assets, Handle, Image, NextState, Res, ResMut},
     sprite::{TextureAtlas, TextureAtlasBuilder},
     utils::HashMap,
 };
 
 use crate::{
-    data::entity_types::{EntityImage, EntityTypes, Loaded, LoadedAnimations},
+    components::animation::DEFAULT_ANIMATION,
+    data::entity_types::{
+        EntityImage, EntityType, EntityTypes, Frame, Frames, Loaded, LoadedAnimation,
+        LoadedAnimations,
+    },
     AppState, ImageHandles,
 };
 
@@ -18,27 +23,54 @@ pub fn load_textures(
     asset_server: Res<AssetServer>,
 ) {
     for entity_type in entity_types.map.values_mut() {
-        match &entity_type.image {
-            EntityImage::Static(image) => {
-                let handle = asset_server.load::<Image, _>(&format!("entities/{image}"));
-                image_handles.add(handle.clone());
-                entity_type.loaded = Some(Loaded::Static(handle));
-            }
-            EntityImage::Animations(animations) => {
-                for animation in animations.values() {
-                    for frame in animation.iter() {
-                        let image = &frame.image;
-                        let handle = asset_server.load::<Image, _>(&format!("entities/{image}"));
-                        image_handles.add(handle.clone());
-                        entity_type.loaded = Some(Loaded::Static(handle));
-                    }
-                }
-            }
-            _ => unimplemented!(),
+        for handle in load_entity_type_textures(entity_type, &asset_server) {
+            image_handles.add(handle);
+        }
+    }
+}
+
+/// Start loading the images of an entity type and return their handles.
+/// Static images are assigned to the entity type right away. Animations
+/// need to wait for all images to be loaded before the texture atlas can
+/// be built by `finish_entity_type_textures`.
+pub fn load_entity_type_textures(
+    entity_type: &mut EntityType,
+    asset_server: &AssetServer,
+) -> Vec<Handle<Image>> {
+    match &entity_type.image {
+        EntityImage::Static(image) => {
+            let handle = asset_server.load::<Image, _>(&format!("entities/{image}"));
+            entity_type.loaded = Some(Loaded::Static(handle.clone()));
+            vec![handle]
+        }
+        EntityImage::Animation(frames) => load_frames(frames.iter(), asset_server),
+        EntityImage::Animations(animations) => load_frames(
+            animations.values().flat_map(|animation| animation.iter()),
+            asset_server,
+        ),
+        EntityImage::SpriteSheet(sprite_sheet) => {
+            let image = &sprite_sheet.image;
+            vec![asset_server.load::<Image, _>(&format!("entities/{image}"))]
+        }
+        EntityImage::Aseprite(aseprite) => {
+            // The sheet is always set by the EntityTypeLoader.
+            vec![asset_server.load::<Image, _>(aseprite.image_path().unwrap())]
         }
     }
 }
 
+fn load_frames<'a>(
+    frames: impl Iterator<Item = &'a Frame>,
+    asset_server: &AssetServer,
+) -> Vec<Handle<Image>> {
+    frames
+        .map(|frame| {
+            let image = &frame.image;
+            asset_server.load::<Image, _>(&format!("entities/{image}"))
+        })
+        .collect()
+}
+
 pub fn check_textures(
     mut state: ResMut<NextState<AppState>>,
     image_handles: ResMut<ImageHandles>,
@@ -53,53 +85,142 @@ pub fn check_textures(
         state.set(AppState::Finished);
 
         for entity_type in entity_types.map.values_mut() {
-            match &entity_type.image {
-                EntityImage::Static(_) => {
-                    // The handle was already assigned in the load_textures method.
-                }
-                EntityImage::Animations(animations) => {
-                    let mut atlas_builder = TextureAtlasBuilder::default();
-                    let frame_handles: HashMap<String, Vec<(Handle<Image>, Duration)>> = animations
-                        .iter()
-                        .map(|(animation_name, frames)| {
-                            (
-                                animation_name.clone(),
-                                frames
-                                    .iter()
-                                    .map(|frame| {
-                                        let file_name = format!("entities/{}", frame.image);
-                                        let handle = asset_server.get_handle(file_name);
-                                        let texture = textures.get(&handle).unwrap();
-                                        atlas_builder.add_texture(handle.clone(), texture);
-                                        (handle, Duration::from_millis(frame.duration))
-                                    })
-                                    .collect(),
-                            )
-                        })
-                        .collect();
-                    let atlas = atlas_builder.finish(&mut textures).unwrap();
-                    let atlas_handle = texture_atlases.add(atlas);
-                    let atlas = texture_atlases.get(&atlas_handle).unwrap();
-                    entity_type.loaded = Some(Loaded::Animations(LoadedAnimations {
-                        atlas: atlas_handle,
-                        frames: frame_handles
-                            .into_iter()
-                            .map(|(animation_name, frames)| {
-                                (
-                                    animation_name,
-                                    frames
-                                        .into_iter()
-                                        .map(|(handle, duration)| {
-                                            (atlas.get_texture_index(&handle).unwrap(), duration)
-                                        })
-                                        .collect(),
-                                )
-                            })
-                            .collect(),
-                    }));
-                }
-                _ => unimplemented!(),
+            finish_entity_type_textures(
+                entity_type,
+                &asset_server,
+                &mut textures,
+                &mut texture_atlases,
+            );
+        }
+    }
+}
+
+/// Build the texture atlas of an animated entity type. All images
+/// returned by `load_entity_type_textures` must be loaded at this point.
+pub fn finish_entity_type_textures(
+    entity_type: &mut EntityType,
+    asset_server: &AssetServer,
+    textures: &mut Assets<Image>,
+    texture_atlases: &mut Assets<TextureAtlas>,
+) {
+    match &entity_type.image {
+        EntityImage::Static(_) => {
+            // The handle was already assigned in the load_entity_type_textures method.
+        }
+        EntityImage::Animation(frames) => {
+            let mut atlas_builder = TextureAtlasBuilder::default();
+            let frame_handles = add_frames(&mut atlas_builder, frames, asset_server, textures);
+            let atlas = atlas_builder.finish(textures).unwrap();
+            let atlas_handle = texture_atlases.add(atlas);
+            let atlas = texture_atlases.get(&atlas_handle).unwrap();
+            entity_type.loaded = Some(Loaded::Animation(LoadedAnimation {
+                atlas: atlas_handle,
+                frames: frame_indices(atlas, frame_handles),
+            }));
+        }
+        EntityImage::Animations(animations) => {
+            let mut atlas_builder = TextureAtlasBuilder::default();
+            let frame_handles: HashMap<String, Vec<(Handle<Image>, Duration)>> = animations
+                .iter()
+                .map(|(animation_name, frames)| {
+                    (
+                        animation_name.clone(),
+                        add_frames(&mut atlas_builder, frames, asset_server, textures),
+                    )
+                })
+                .collect();
+            let atlas = atlas_builder.finish(textures).unwrap();
+            let atlas_handle = texture_atlases.add(atlas);
+            let atlas = texture_atlases.get(&atlas_handle).unwrap();
+            entity_type.loaded = Some(Loaded::Animations(LoadedAnimations {
+                atlas: atlas_handle,
+                frames: frame_handles
+                    .into_iter()
+                    .map(|(animation_name, frames)| (animation_name, frame_indices(atlas, frames)))
+                    .collect(),
+            }));
+        }
+        EntityImage::SpriteSheet(sprite_sheet) => {
+            let file_name = format!("entities/{}", sprite_sheet.image);
+            let atlas = TextureAtlas::from_grid(
+                asset_server.get_handle(file_name),
+                Vec2::new(
+                    sprite_sheet.tile.width.into(),
+                    sprite_sheet.tile.height.into(),
+                ),
+                sprite_sheet.columns,
+                sprite_sheet.rows,
+                sprite_sheet
+                    .padding
+                    .map(|padding| Vec2::new(padding.width.into(), padding.height.into())),
+                sprite_sheet
+                    .offset
+                    .map(|offset| Vec2::new(offset.x.into(), offset.y.into())),
+            );
+            entity_type.loaded = Some(Loaded::Animations(LoadedAnimations {
+                atlas: texture_atlases.add(atlas),
+                frames: sprite_sheet
+                    .animations
+                    .iter()
+                    .map(|(animation_name, animation)| {
+                        let duration = Duration::from_millis(animation.duration);
+                        (
+                            animation_name.clone(),
+                            (animation.first..=animation.last)
+                                .map(|index| (index, duration))
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+            }));
+        }
+        EntityImage::Aseprite(aseprite) => {
+            let sheet = aseprite.sheet.as_ref().unwrap();
+            let mut atlas = TextureAtlas::new_empty(
+                asset_server.get_handle(AssetPath::from(aseprite.image_path().unwrap())),
+                Vec2::new(sheet.meta.size.w as f32, sheet.meta.size.h as f32),
+            );
+            for frame in sheet.frames.as_slice() {
+                let rect = frame.frame;
+                atlas.add_texture(Rect::new(
+                    rect.x as f32,
+                    rect.y as f32,
+                    (rect.x + rect.w) as f32,
+                    (rect.y + rect.h) as f32,
+                ));
             }
+            entity_type.loaded = Some(Loaded::Animations(LoadedAnimations {
+                atlas: texture_atlases.add(atlas),
+                frames: sheet.animations(DEFAULT_ANIMATION),
+            }));
         }
     }
 }
+
+fn add_frames(
+    atlas_builder: &mut TextureAtlasBuilder,
+    frames: &Frames,
+    asset_server: &AssetServer,
+    textures: &Assets<Image>,
+) -> Vec<(Handle<Image>, Duration)> {
+    frames
+        .iter()
+        .map(|frame| {
+            let file_name = format!("entities/{}", frame.image);
+            let handle = asset_server.get_handle(file_name);
+            let texture = textures.get(&handle).unwrap();
+            atlas_builder.add_texture(handle.clone(), texture);
+            (handle, Duration::from_millis(frame.duration))
+        })
+        .collect()
+}
+
+fn frame_indices(
+    atlas: &TextureAtlas,
+    frames: Vec<(Handle<Image>, Duration)>,
+) -> Vec<(usize, Duration)> {
+    frames
+        .into_iter()
+        .map(|(handle, duration)| (atlas.get_texture_index(&handle).unwrap(), duration))
+        .collect()
+}
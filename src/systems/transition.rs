@@ -0,0 +1,181 @@
+use bevy::{
+    asset::LoadState,
+    log::{error, warn},
+    prelude::*,
+    tasks::AsyncComputeTaskPool,
+};
+use futures_lite::future;
+
+use crate::{
+    asset_dir,
+    components::{
+        collision::Collision,
+        map::{FadeOverlay, MapBackground, MapEntity},
+        player::{Player, PLAYER_SPAWN},
+        trigger::Trigger,
+    },
+    data::{
+        entity_types::EntityTypes,
+        map::{door_target, Map, MapHandle, RegistryMaps},
+    },
+    load_collision_map, load_spawn_chunks,
+    resources::{
+        config::Config,
+        map::Map as CollisionMap,
+        map_registry::MapRegistry,
+        transition::{MapTransition, TransitionPhase},
+    },
+    systems::{
+        map::{despawn_map_entities, spawn_map_background, spawn_map_entities},
+        trigger::{TriggerEvent, TriggerEventKind},
+    },
+};
+
+/// Start a transition to another map when the player enters a door
+pub fn use_doors(
+    mut commands: Commands,
+    registry: Res<MapRegistry>,
+    registry_maps: Res<RegistryMaps>,
+    transition: Option<Res<MapTransition>>,
+    player_query: Query<Entity, With<Player>>,
+    mut trigger_events: EventReader<TriggerEvent>,
+) {
+    let player = player_query.single();
+    let door = trigger_events
+        .iter()
+        .filter(|event| event.entity == player && event.kind == TriggerEventKind::Enter)
+        .find_map(|event| door_target(&event.trigger));
+    // Doors entered while the map is changing are ignored
+    let Some(door) = door.filter(|_| transition.is_none()) else {
+        return;
+    };
+    let (Some(files), Some(handle)) = (registry.get(door.map), registry_maps.handles.get(door.map))
+    else {
+        warn!("Door leads to unknown map {:?}", door.map);
+        return;
+    };
+    commands.insert_resource(MapTransition::new(
+        files.clone(),
+        handle.clone(),
+        door.spawn_point.map(str::to_owned),
+    ));
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                ..Default::default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.0).into(),
+            z_index: ZIndex::Global(i32::MAX),
+            ..Default::default()
+        },
+        FadeOverlay,
+    ));
+}
+
+/// Fade the screen out, replace the current map by the map of the
+/// transition, move the player to the spawn point and fade in again.
+/// The collision map is loaded in the background while the screen is
+/// black. The current map is kept if the new map can't be loaded.
+#[allow(clippy::too_many_arguments)]
+pub fn map_transition(
+    mut commands: Commands,
+    time: Res<Time>,
+    config: Res<Config>,
+    asset_server: Res<AssetServer>,
+    maps: Res<Assets<Map>>,
+    entity_types: Res<EntityTypes>,
+    mut transition: ResMut<MapTransition>,
+    mut map_handle: ResMut<MapHandle>,
+    map_entities: Query<Entity, With<MapEntity>>,
+    triggers: Query<(Entity, &Trigger)>,
+    backgrounds: Query<Entity, With<MapBackground>>,
+    mut player_query: Query<(&mut Transform, &mut Collision), With<Player>>,
+    mut overlays: Query<(Entity, &mut BackgroundColor), With<FadeOverlay>>,
+    mut trigger_events: EventWriter<TriggerEvent>,
+) {
+    transition.timer.tick(time.delta());
+    for (_, mut color) in overlays.iter_mut() {
+        color.0.set_a(transition.alpha());
+    }
+    match transition.phase {
+        TransitionPhase::FadeOut if transition.timer.finished() => {
+            transition.advance();
+        }
+        TransitionPhase::Loading => {
+            let Some(map) = maps.get(&transition.handle) else {
+                // The maps are loaded and checked at startup, but a
+                // reload may have failed since
+                if let LoadState::NotLoaded | LoadState::Loading =
+                    asset_server.get_load_state(&transition.handle)
+                {
+                    return;
+                }
+                error!("Loading map {:?} failed", transition.map.map);
+                transition.advance();
+                return;
+            };
+            let files = transition.map.clone();
+            let task = transition.task.get_or_insert_with(|| {
+                let (map, config) = (map.clone(), config.clone());
+                AsyncComputeTaskPool::get().spawn(async move {
+                    let mut collision_map =
+                        load_collision_map(&asset_dir(), &files, &map, &config)?;
+                    load_spawn_chunks(&mut collision_map, &map)?;
+                    Ok(collision_map)
+                })
+            });
+            let Some(collision_map) = future::block_on(future::poll_once(task)) else {
+                return;
+            };
+            transition.task = None;
+            let files = &transition.map;
+            let collision_map = match collision_map {
+                Ok(collision_map) => collision_map,
+                Err(error) => {
+                    error!("Loading the collision map failed: {}", error);
+                    transition.advance();
+                    return;
+                }
+            };
+
+            despawn_map_entities(&mut commands, &map_entities, &triggers, &mut trigger_events);
+            for entity in backgrounds.iter() {
+                commands.entity(entity).despawn_recursive();
+            }
+            spawn_map_entities(&mut commands, map, &entity_types);
+            // The background of chunked maps is streamed
+            if !matches!(collision_map, CollisionMap::Chunked(_)) {
+                spawn_map_background(&mut commands, &asset_server, map, &files.map);
+            }
+            commands.insert_resource(collision_map);
+            commands.insert_resource(map.clone());
+            *map_handle = MapHandle {
+                handle: transition.handle.clone(),
+                map_file: files.map.clone(),
+            };
+
+            let position = transition
+                .spawn_point
+                .as_ref()
+                .and_then(|name| map.spawn_points.get(name))
+                .map_or(PLAYER_SPAWN, |spawn_point| {
+                    let position = spawn_point.position;
+                    Vec3::new(position.x.into(), position.y.into(), 0.0)
+                });
+            let (mut transform, mut collision) = player_query.single_mut();
+            let z = collision.update_position(position);
+            transform.translation = Vec3::new(position.x, position.y, z);
+            transition.advance();
+        }
+        TransitionPhase::FadeIn if transition.timer.finished() => {
+            for (entity, _) in overlays.iter() {
+                commands.entity(entity).despawn_recursive();
+            }
+            commands.remove_resource::<MapTransition>();
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,59 @@
+use bevy::{
+    prelude::{Entity, Event, EventWriter, Query, Res, Without},
+    utils::HashSet,
+};
+
+use crate::{
+    components::{collision::Collision, trigger::Trigger},
+    resources::collision_index::CollisionIndex,
+};
+
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct TriggerEvent {
+    /// Name of the trigger zone
+    pub trigger: String,
+    /// Entity entering or leaving the zone
+    pub entity: Entity,
+    pub kind: TriggerEventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerEventKind {
+    Enter,
+    Exit,
+}
+
+/// Send events for colliders entering or leaving trigger zones. Only
+/// colliders matching the collision mask of the zone are detected.
+pub fn detect_triggers(
+    mut triggers: Query<(&mut Trigger, &Collision)>,
+    colliders: Query<&Collision, Without<Trigger>>,
+    collision_index: Res<CollisionIndex>,
+    mut events: EventWriter<TriggerEvent>,
+) {
+    for (mut trigger, sensor) in triggers.iter_mut() {
+        let (min, max) = sensor.world_bounds();
+        let inside = collision_index
+            .query(min, max)
+            .into_iter()
+            .filter(|entity| {
+                colliders.get(*entity).is_ok_and(|collision| {
+                    sensor.interacts(collision) && sensor.overlaps(collision)
+                })
+            })
+            .collect::<HashSet<_>>();
+        for (entities, kind) in [
+            (inside.difference(&trigger.inside), TriggerEventKind::Enter),
+            (trigger.inside.difference(&inside), TriggerEventKind::Exit),
+        ] {
+            for entity in entities {
+                events.send(TriggerEvent {
+                    trigger: trigger.name.clone(),
+                    entity: *entity,
+                    kind,
+                });
+            }
+        }
+        trigger.inside = inside;
+    }
+}